@@ -0,0 +1,247 @@
+use std::fs::{read_to_string, write};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::CONFIG;
+use crate::mask;
+use crate::user::User;
+
+pub static BANS: Lazy<Arc<Mutex<BanList>>> = Lazy::new(|| {
+    let config = CONFIG.lock().unwrap().clone();
+    let mut bans = BanList::load(config.server.ban_file);
+    for entry in config.xline {
+        bans.add_config(Ban::new(BanKind::Xline, entry.mask, entry.reason, config.server.name.clone(), None));
+    }
+    for entry in config.qline {
+        bans.add_config(Ban::new(BanKind::Qline, entry.mask, entry.reason, config.server.name.clone(), None));
+    }
+    Arc::new(Mutex::new(bans))
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BanKind {
+    /// Bans a `user@host` mask from this server.
+    Kline,
+    /// Bans a `user@host` mask from the whole network.
+    Gline,
+    /// Bans an IP address or CIDR block before a handler is even spawned.
+    Dline,
+    /// Bans users whose realname matches a wildcard or `/regex/` mask.
+    Xline,
+    /// Reserves nicknames, or channel names when the mask starts with `#`.
+    Qline,
+    /// Silences a `user@host` mask without disconnecting it.
+    Shun,
+}
+
+impl BanKind {
+    pub fn letter(&self) -> char {
+        match self {
+            BanKind::Kline => 'K',
+            BanKind::Gline => 'G',
+            BanKind::Dline => 'D',
+            BanKind::Xline => 'X',
+            BanKind::Qline => 'Q',
+            BanKind::Shun => 'S',
+        }
+    }
+
+    /// How the ban is called in notices, e.g. `K-line` or `shun`.
+    pub fn name(&self) -> String {
+        match self {
+            BanKind::Shun => "shun".to_string(),
+            kind => format!("{}-line", kind.letter()),
+        }
+    }
+
+    /// Whether users matching a ban of this kind get disconnected.
+    pub fn disconnects(&self) -> bool {
+        return matches!(self, BanKind::Kline | BanKind::Gline | BanKind::Dline | BanKind::Xline);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub kind: BanKind,
+    pub mask: String,
+    pub reason: String,
+    pub setter: String,
+    pub set_at: u64,
+    pub expires_at: Option<u64>,
+    /// `mask` compiled once, when it is a `/regex/`.
+    #[serde(skip)]
+    regex: Option<Regex>,
+}
+
+impl Ban {
+    /// A ban set now, lasting `duration` seconds or forever when `None`.
+    pub fn new(kind: BanKind, mask: String, reason: String, setter: String, duration: Option<u64>) -> Self {
+        let set_at = now();
+        let regex = mask::compile_pattern(&mask);
+        return Ban { kind, mask, reason, setter, set_at, expires_at: duration.map(|duration| set_at.saturating_add(duration)), regex };
+    }
+
+    /// Matches `text` against the wildcard or `/regex/` mask.
+    fn matches_pattern(&self, text: &str) -> bool {
+        if mask::is_regex(&self.mask) {
+            return self.regex.as_ref().is_some_and(|regex| regex.is_match(text));
+        }
+        return mask::matches(&self.mask, text);
+    }
+
+    pub fn is_expired(&self) -> bool {
+        return self.expires_at.is_some_and(|expires_at| expires_at <= now());
+    }
+
+    pub fn matches_address(&self, ip_address: IpAddr) -> bool {
+        return self.kind == BanKind::Dline && mask::matches_address(&self.mask, ip_address);
+    }
+
+    pub fn matches_user(&self, user: &User) -> bool {
+        match self.kind {
+            BanKind::Dline => mask::matches_address(&self.mask, user.ip_address),
+            BanKind::Kline | BanKind::Gline | BanKind::Shun => {
+                let (user_mask, host_mask) = self.mask.rsplit_once('@').unwrap_or(("*", &self.mask));
+                mask::matches(user_mask, &user.username) && user.matches_any_host(host_mask)
+            },
+            BanKind::Xline => self.matches_pattern(&user.realname),
+            BanKind::Qline => false,
+        }
+    }
+
+    pub fn matches_nickname(&self, nickname: &str) -> bool {
+        return self.kind == BanKind::Qline && !self.mask.starts_with('#')
+            && self.matches_pattern(nickname);
+    }
+
+    pub fn matches_channel(&self, channel_name: &str) -> bool {
+        return self.kind == BanKind::Qline && self.mask.starts_with('#')
+            && self.matches_pattern(channel_name);
+    }
+
+    /// The reason shown to a client that gets disconnected by this ban.
+    pub fn disconnect_reason(&self) -> String {
+        return format!("{}-lined: {}", self.kind.letter(), self.reason);
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct BanFile {
+    #[serde(default)]
+    ban: Vec<Ban>,
+}
+
+/// Every server ban, written back to `path` whenever it changes. Bans taken
+/// from `Config` are kept apart and never written.
+pub struct BanList {
+    path: Option<String>,
+    bans: Vec<Ban>,
+    config_bans: Vec<Ban>,
+}
+
+impl BanList {
+    pub fn load(path: Option<String>) -> Self {
+        let bans = match &path {
+            Some(path) => match read_to_string(path) {
+                Ok(contents) => match toml::from_str::<BanFile>(&contents) {
+                    Ok(file) => file.ban,
+                    Err(err) => {
+                        warn!("Could not load bans from file \"{}\": {}", path, err);
+                        Vec::new()
+                    },
+                },
+                Err(_) => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+        let mut list = BanList { path, bans, config_bans: Vec::new() };
+        list.bans.retain(|ban| !ban.is_expired());
+        for ban in list.bans.iter_mut() {
+            ban.regex = mask::compile_pattern(&ban.mask);
+        }
+        info!("Loaded {} bans", list.bans.len());
+        return list;
+    }
+
+    /// Adds `ban`, replacing any ban of the same kind on the same mask.
+    pub fn add(&mut self, ban: Ban) {
+        self.bans.retain(|existing| !(existing.kind == ban.kind && existing.mask.eq_ignore_ascii_case(&ban.mask)));
+        self.bans.push(ban);
+        self.save();
+    }
+
+    pub fn add_config(&mut self, ban: Ban) {
+        self.config_bans.push(ban);
+    }
+
+    pub fn remove(&mut self, kind: BanKind, mask: &str) -> bool {
+        let count = self.bans.len();
+        self.bans.retain(|ban| !(ban.kind == kind && ban.mask.eq_ignore_ascii_case(mask)));
+        if self.bans.len() == count {
+            return false;
+        }
+        self.save();
+        return true;
+    }
+
+    pub fn list(&self, kind: BanKind) -> Vec<Ban> {
+        return self.active()
+            .filter(|ban| ban.kind == kind)
+            .cloned()
+            .collect();
+    }
+
+    pub fn find_address(&self, ip_address: IpAddr) -> Option<Ban> {
+        return self.active().find(|ban| ban.matches_address(ip_address)).cloned();
+    }
+
+    /// The first K-line, G-line, D-line or X-line matching a registered user.
+    pub fn find_user(&self, user: &User) -> Option<Ban> {
+        return self.active().find(|ban| ban.kind.disconnects() && ban.matches_user(user)).cloned();
+    }
+
+    pub fn find_shun(&self, user: &User) -> Option<Ban> {
+        return self.active().find(|ban| ban.kind == BanKind::Shun && ban.matches_user(user)).cloned();
+    }
+
+    pub fn find_nickname(&self, nickname: &str) -> Option<Ban> {
+        return self.active().find(|ban| ban.matches_nickname(nickname)).cloned();
+    }
+
+    pub fn find_channel(&self, channel_name: &str) -> Option<Ban> {
+        return self.active().find(|ban| ban.matches_channel(channel_name)).cloned();
+    }
+
+    fn active(&self) -> impl Iterator<Item = &Ban> {
+        return self.config_bans.iter()
+            .chain(self.bans.iter())
+            .filter(|ban| !ban.is_expired());
+    }
+
+    fn save(&mut self) {
+        self.bans.retain(|ban| !ban.is_expired());
+        let Some(path) = &self.path else {
+            return;
+        };
+        let file = BanFile { ban: self.bans.clone() };
+        match toml::to_string(&file) {
+            Ok(contents) => {
+                if let Err(err) = write(path, contents) {
+                    warn!("Could not write bans to file \"{}\": {}", path, err);
+                }
+            },
+            Err(err) => warn!("Could not serialize bans: {}", err),
+        }
+    }
+}
+
+/// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+}
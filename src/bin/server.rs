@@ -1,10 +1,17 @@
 use irc_proto::enable_logging;
 use tokio::net::TcpListener;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use log::info;
+use once_cell::sync::Lazy;
 
+use irc_server::ban::BANS;
+use irc_server::spamfilter::SPAMFILTERS;
+use irc_server::motd::MOTD;
+use irc_server::resolver::SystemResolver;
 use irc_server::server::run;
 use irc_server::config::CONFIG;
+use irc_server::websocket::WebSocketAcceptor;
 
 #[tokio::main]
 async fn main() -> Result<(), ()> {
@@ -12,6 +19,7 @@ async fn main() -> Result<(), ()> {
 
     let config = CONFIG.lock().unwrap();
     let server_addr: SocketAddr = SocketAddr::new(config.server.address_v4, config.server.port);
+    let websocket_configs = config.websocket.clone();
     drop(config);
 
     let listener = match TcpListener::bind(server_addr).await {
@@ -19,5 +27,25 @@ async fn main() -> Result<(), ()> {
         Err(e) => panic!("{}", e),
     };
     info!("Server started at {:}", server_addr);
-    run(listener, tokio::signal::ctrl_c()).await
+
+    Lazy::force(&BANS);
+    Lazy::force(&SPAMFILTERS);
+    Lazy::force(&MOTD);
+
+    let mut websockets = Vec::new();
+    for websocket in websocket_configs {
+        let websocket_addr = SocketAddr::new(websocket.address, websocket.port);
+        let acceptor = match WebSocketAcceptor::from_config(&websocket) {
+            Ok(a) => a,
+            Err(e) => panic!("{}", e),
+        };
+        let listener = match TcpListener::bind(websocket_addr).await {
+            Ok(l) => l,
+            Err(e) => panic!("{}", e),
+        };
+        info!("WebSocket listener started at {:}", websocket_addr);
+        websockets.push((listener, acceptor));
+    }
+
+    run(listener, websockets, Arc::new(SystemResolver::new()), tokio::signal::ctrl_c()).await
 }
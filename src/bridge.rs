@@ -1,18 +1,58 @@
 use irc_proto::{channel::Channel, types::{Command::{self, *}, Message, Source}};
-use tokio::sync::mpsc;
-use std::collections::HashMap;
+use tokio::sync::{mpsc, oneshot};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
 
-use crate::user::User;
+use crate::ban::{now, Ban};
+use crate::config::CONFIG;
+use crate::isupport;
+use crate::list::{ListEntry, ListQuery};
+use crate::mask;
+use crate::sendq::SendQ;
+use crate::snomask::Snomask;
+use crate::user::{Capabilities, User, UserModes};
 
 
-pub type HandlerTxMap = HashMap<String, mpsc::Sender<Message>>;
+pub type HandlerTxMap = HashMap<String, SendQ>;
 pub type ChannelMap = HashMap<String, Channel>;
+pub type UserMap = HashMap<String, User>;
+
+/// How many departed nicknames WHOWAS remembers.
+const WHOWAS_LENGTH: usize = 128;
+
+/// The key a nickname or channel name is stored under, so that lookups
+/// follow the `ascii` casemapping advertised in RPL_ISUPPORT.
+fn key(name: &str) -> String {
+    return name.to_ascii_lowercase();
+}
 
 #[derive(Debug)]
 pub enum OperMsg {
-    AddUser{name: String, channel: mpsc::Sender<Message>},
-    DeleteUser{name: String},
-    JoinChannel{nickname: String, channel_name: String}
+    /// Answered on `accepted` with false when the nickname is in use.
+    AddUser{name: String, channel: SendQ, user: User, accepted: oneshot::Sender<bool>},
+    UpdateUser{user: User},
+    /// Answered on `accepted` with false when the nickname is in use.
+    ChangeNick{old_nickname: String, user: User, accepted: oneshot::Sender<bool>},
+    DeleteUser{name: String, reason: String},
+    ServerNotice{snomask: Snomask, text: String},
+    EnforceBan{ban: Ban},
+    JoinChannel{nickname: String, channel_name: String},
+    List{nickname: String, query: Option<String>},
+    Who{nickname: String, mask: String, options: Option<String>},
+    /// WALLOPS, sent to users with +w.
+    Wallops{nickname: String, text: String},
+    /// OPERWALL or GLOBOPS, sent to operators.
+    Operwall{nickname: String, text: String},
+    /// A NOTICE to `$mask`, sent to everyone on servers matching the mask.
+    Announce{nickname: String, mask: String, text: String},
+    Whois{nickname: String, targets: String},
+    /// MODE on a channel or on another user, which is only answered.
+    Mode{nickname: String, target: String},
+    Whowas{nickname: String, target: String, count: Option<usize>},
+    /// LUSERS, with the number of open connections, registered or not.
+    Lusers{nickname: String, connections: usize},
+    /// RPL_ISUPPORT tokens that changed on a rehash, sent to every user.
+    Isupport{tokens: Vec<String>},
 }
 
 #[derive(Debug)]
@@ -21,58 +61,734 @@ pub struct CommMsg {
     pub msg: Message,
 }
 
+/// A user as they were when they quit or changed nickname.
+#[derive(Debug, Clone)]
+pub struct Whowas {
+    pub nickname: String,
+    pub username: String,
+    pub host: String,
+    pub realname: String,
+}
+
+/// Maps are keyed by `key`, while the values keep the names as given.
 pub struct Bridge {
     pub handler_tx_map: HandlerTxMap,
     pub channel_map: ChannelMap,
+    pub user_map: UserMap,
+    /// When each channel was created, in seconds since the Unix epoch.
+    pub channel_created: HashMap<String, u64>,
+    pub whowas: VecDeque<Whowas>,
+    /// The most users that have been registered at once.
+    pub max_users: usize,
+    /// Users whose sendq overflowed, dropped once the current message has
+    /// been handled.
+    exceeded: Mutex<Vec<String>>,
     pub oper_rx: mpsc::Receiver<OperMsg>,
     pub comm_rx: mpsc::Receiver<CommMsg>,
 }
 
 impl Bridge {
     pub fn new(oper_rx: mpsc::Receiver<OperMsg>, comm_rx: mpsc::Receiver<CommMsg>) -> Self {
-        return Bridge { handler_tx_map: HashMap::new(), channel_map: HashMap::new(), oper_rx, comm_rx }
+        return Bridge {
+            handler_tx_map: HashMap::new(),
+            channel_map: HashMap::new(),
+            user_map: HashMap::new(),
+            channel_created: HashMap::new(),
+            whowas: VecDeque::new(),
+            max_users: 0,
+            exceeded: Mutex::new(Vec::new()),
+            oper_rx,
+            comm_rx,
+        }
+    }
+
+    /// The `nick!user@host` prefix of a connected user.
+    fn source(&self, nickname: &str) -> Source {
+        match self.user_map.get(&key(nickname)) {
+            Some(user) => user.source(),
+            None => Source{name: nickname.to_string(), user: None, host: None},
+        }
+    }
+
+    /// Queues `message` for a connected user. A user whose sendq overflows
+    /// is dropped once the current message has been handled.
+    fn send(&self, nickname: &str, message: Message) {
+        if let Some(sendq) = self.handler_tx_map.get(&key(nickname)) {
+            if sendq.push(message).is_err() {
+                self.exceeded.lock().unwrap().push(nickname.to_string());
+            }
+        }
+    }
+
+    /// Sends a server numeric or notice to a connected user.
+    fn reply(&self, nickname: &str, command: Command) {
+        let source = CONFIG.lock().unwrap().server.source();
+        self.send(nickname, Message::new(None, Some(source), command));
+    }
+
+    /// Sends a batch of server replies to a connected user.
+    fn deliver(&self, nickname: &str, replies: Vec<Command>) {
+        let source = CONFIG.lock().unwrap().server.source();
+        for command in replies {
+            self.send(nickname, Message::new(None, Some(source.clone()), command));
+        }
+    }
+
+    fn remember(&mut self, user: &User) {
+        if self.whowas.len() == WHOWAS_LENGTH {
+            self.whowas.pop_front();
+        }
+        self.whowas.push_back(Whowas {
+            nickname: user.nickname.clone(),
+            username: user.username.clone(),
+            host: user.visible_host().to_string(),
+            realname: user.realname.clone(),
+        });
+    }
+
+    /// Sends `text` to every operator whose snomask includes `snomask`.
+    fn server_notice(&self, snomask: Snomask, text: &str) {
+        let source = CONFIG.lock().unwrap().server.source();
+        for user in self.user_map.values() {
+            if user.oper.is_none() || !user.snomask.contains(snomask) {
+                continue;
+            }
+            self.send(&user.nickname, Message::new(
+                None,
+                Some(source.clone()),
+                Command::NOTICE{
+                    targets: user.nickname.clone(),
+                    text: format!("*** Notice -- {}", text),
+                }
+            ));
+        }
+    }
+
+    /// Sends `command` from `nickname` to every user `recipient` accepts.
+    fn broadcast(&self, nickname: &str, command: Command, recipient: impl Fn(&User) -> bool) {
+        let source = self.source(nickname);
+        for user in self.user_map.values().filter(|user| recipient(user)) {
+            self.send(&user.nickname, Message::new(None, Some(source.clone()), command.clone()));
+        }
+    }
+
+    /// Tells channel co-members with away-notify that `nickname` went away
+    /// or came back.
+    fn away_notify(&self, nickname: &str) {
+        let Some(user) = self.user_map.get(&key(nickname)) else {
+            return;
+        };
+        let recipients: HashSet<&String> = self.channel_map.values()
+            .filter(|channel| channel.members.iter().any(|member| member == nickname))
+            .flat_map(|channel| channel.members.iter())
+            .filter(|member| *member != nickname)
+            .collect();
+        for recipient in recipients {
+            let notify = self.user_map.get(&key(recipient))
+                .is_some_and(|recipient| recipient.capabilities.contains(Capabilities::AWAY_NOTIFY));
+            if notify {
+                self.send(recipient, Message::new(
+                    None,
+                    Some(user.source()),
+                    AWAY{text: user.away.clone()},
+                ));
+            }
+        }
+    }
+
+    /// Answers LIST.
+    fn list(&self, nickname: String, query: Option<String>) {
+        let query = ListQuery::parse(&query.unwrap_or_default());
+        let now = now();
+        let mut channels: Vec<&Channel> = self.channel_map.values()
+            .filter(|channel| query.matches(&ListEntry {
+                name: &channel.name,
+                users: channel.members.len(),
+                created: self.channel_created.get(&key(&channel.name)).copied().unwrap_or(now),
+                topic_time: None,
+            }, now))
+            .collect();
+        channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut replies = vec![RPL_LISTSTART {
+            client: nickname.clone(),
+            channel: "Channel".to_string(),
+            text: "Users  Name".to_string(),
+        }];
+        replies.extend(channels.into_iter().map(|channel| RPL_LIST {
+            client: nickname.clone(),
+            channel: channel.name.clone(),
+            count: channel.members.len().to_string(),
+            topic: String::new(),
+        }));
+        replies.push(RPL_LISTEND {
+            client: nickname.clone(),
+            text: "End of /LIST".to_string(),
+        });
+
+        self.deliver(&nickname, replies);
+    }
+
+    /// Answers WHO for a channel or a mask over nicknames, usernames, hosts,
+    /// realnames and the server name. `options` may hold the `o` flag and a
+    /// WHOX `%fields,token` selection.
+    fn who(&self, nickname: String, mask: String, options: Option<String>) {
+        let Some(requester) = self.user_map.get(&key(&nickname)) else {
+            return;
+        };
+        let is_oper = requester.oper.is_some();
+        let server_name = CONFIG.lock().unwrap().server.name.clone();
+        let options = options.unwrap_or_default();
+        let (flags, whox) = match options.split_once('%') {
+            Some((flags, whox)) => (flags, Some(whox)),
+            None => (options.as_str(), None),
+        };
+        let (fields, token) = match whox {
+            Some(whox) => match whox.split_once(',') {
+                Some((fields, token)) => (Some(fields), token),
+                None => (Some(whox), "0"),
+            },
+            None => (None, "0"),
+        };
+
+        let mut matches: Vec<(&str, &User)> = Vec::new();
+        if mask.starts_with('#') || mask.starts_with('&') {
+            if let Some(channel) = self.channel_map.get(&key(&mask)) {
+                let is_member = channel.members.contains(&nickname);
+                for member in &channel.members {
+                    let Some(user) = self.user_map.get(&key(member)) else {
+                        continue;
+                    };
+                    if is_member || is_oper || !user.modes.contains(UserModes::INVISIBLE) {
+                        matches.push((&channel.name, user));
+                    }
+                }
+            }
+        } else {
+            let shared: HashSet<&String> = self.channel_map.values()
+                .filter(|channel| channel.members.contains(&nickname))
+                .flat_map(|channel| channel.members.iter())
+                .collect();
+            let pattern = if mask == "0" { "*" } else { mask.as_str() };
+            for user in self.user_map.values() {
+                let visible = !user.modes.contains(UserModes::INVISIBLE) || is_oper || user.nickname == nickname
+                    || shared.contains(&user.nickname);
+                let matched = mask::matches(pattern, &user.nickname)
+                    || mask::matches(pattern, &user.username)
+                    || mask::matches(pattern, user.visible_host())
+                    || mask::matches(pattern, &user.realname)
+                    || mask::matches(pattern, &server_name);
+                if visible && matched {
+                    matches.push(("*", user));
+                }
+            }
+            matches.sort_by(|a, b| a.1.nickname.cmp(&b.1.nickname));
+        }
+        if flags.contains('o') {
+            matches.retain(|(_, user)| user.oper.is_some());
+        }
+
+        let mut replies = Vec::new();
+        for (channel, user) in matches {
+            let mut user_flags = if user.away.is_some() { "G" } else { "H" }.to_string();
+            if user.oper.is_some() {
+                user_flags.push('*');
+            }
+            if user.modes.contains(UserModes::BOT) {
+                user_flags.push('B');
+            }
+            let Some(fields) = fields else {
+                replies.push(RPL_WHOREPLY {
+                    client: nickname.clone(),
+                    channel: channel.to_string(),
+                    username: user.username.clone(),
+                    host: user.visible_host().to_string(),
+                    server: server_name.clone(),
+                    nick: user.nickname.clone(),
+                    flags: user_flags,
+                    text: format!("0 {}", user.realname),
+                });
+                continue;
+            };
+
+            // WHOX fields always come back in this order, whatever order
+            // they were asked for in.
+            let privileged = is_oper || user.nickname == nickname;
+            let mut values = Vec::new();
+            for field in "tcuihsnfdlaor".chars().filter(|field| fields.contains(*field)) {
+                values.push(match field {
+                    't' => token.to_string(),
+                    'c' => channel.to_string(),
+                    'u' => user.username.clone(),
+                    'i' if privileged => user.ip_address.to_string(),
+                    'i' => "255.255.255.255".to_string(),
+                    'h' => user.visible_host().to_string(),
+                    's' => server_name.clone(),
+                    'n' => user.nickname.clone(),
+                    'f' => user_flags.clone(),
+                    'd' => "0".to_string(),
+                    'l' if privileged => now().saturating_sub(user.idle_since).to_string(),
+                    'l' => "0".to_string(),
+                    'a' => "0".to_string(),
+                    'o' => "n/a".to_string(),
+                    _ => user.realname.clone(),
+                });
+            }
+            replies.push(RPL_WHOSPCRPL {
+                client: nickname.clone(),
+                fields: values,
+            });
+        }
+        replies.push(RPL_ENDOFWHO {
+            client: nickname.clone(),
+            mask,
+            text: "End of WHO list".to_string(),
+        });
+        self.deliver(&nickname, replies);
+    }
+
+    fn whois(&self, nickname: String, targets: String) {
+        let Some(requester) = self.user_map.get(&key(&nickname)) else {
+            return;
+        };
+        let is_oper = requester.oper.is_some();
+        let server = CONFIG.lock().unwrap().server.clone();
+        let mut replies = Vec::new();
+        for target in targets.split(',') {
+            let Some(user) = self.user_map.get(&key(target)) else {
+                replies.push(ERR_NOSUCHNICK {
+                    client: nickname.clone(),
+                    nick: target.to_string(),
+                    text: "No such nick/channel".to_string(),
+                });
+                continue;
+            };
+
+            replies.push(RPL_WHOISUSER {
+                client: nickname.clone(),
+                nick: user.nickname.clone(),
+                username: user.username.clone(),
+                host: user.visible_host().to_string(),
+                unused: "*".to_string(),
+                realname: user.realname.clone(),
+            });
+            if is_oper || user.nickname == nickname {
+                replies.push(RPL_WHOISHOST {
+                    client: nickname.clone(),
+                    nick: user.nickname.clone(),
+                    text: format!("is connecting from *@{} {}", user.hostname, user.ip_address),
+                });
+            }
+            let mut channels: Vec<&str> = self.channel_map.values()
+                .filter(|channel| channel.members.contains(&user.nickname))
+                .map(|channel| channel.name.as_str())
+                .collect();
+            if !channels.is_empty() {
+                channels.sort();
+                replies.push(RPL_WHOISCHANNELS {
+                    client: nickname.clone(),
+                    nick: user.nickname.clone(),
+                    channels: channels.join(" "),
+                });
+            }
+            replies.push(RPL_WHOISSERVER {
+                client: nickname.clone(),
+                nick: user.nickname.clone(),
+                server: server.name.clone(),
+                info: server.description.clone(),
+            });
+            if let Some(away) = &user.away {
+                replies.push(RPL_AWAY {
+                    client: nickname.clone(),
+                    nick: user.nickname.clone(),
+                    text: away.clone(),
+                });
+            }
+            if user.oper.is_some() {
+                replies.push(RPL_WHOISOPERATOR {
+                    client: nickname.clone(),
+                    nick: user.nickname.clone(),
+                    text: "is an IRC operator".to_string(),
+                });
+            }
+            if user.modes.contains(UserModes::BOT) {
+                replies.push(RPL_WHOISBOT {
+                    client: nickname.clone(),
+                    nick: user.nickname.clone(),
+                    text: "is a Bot".to_string(),
+                });
+            }
+            if user.secure {
+                replies.push(RPL_WHOISSECURE {
+                    client: nickname.clone(),
+                    nick: user.nickname.clone(),
+                    text: "is using a secure connection".to_string(),
+                });
+            }
+            if is_oper {
+                replies.push(RPL_WHOISSPECIAL {
+                    client: nickname.clone(),
+                    nick: user.nickname.clone(),
+                    text: format!("is in connection class {}", user.class),
+                });
+            }
+            replies.push(RPL_WHOISIDLE {
+                client: nickname.clone(),
+                nick: user.nickname.clone(),
+                idle: now().saturating_sub(user.idle_since).to_string(),
+                signon: user.signon.to_string(),
+                text: "seconds idle, signon time".to_string(),
+            });
+        }
+        replies.push(RPL_ENDOFWHOIS {
+            client: nickname.clone(),
+            nick: targets,
+            text: "End of /WHOIS list".to_string(),
+        });
+        self.deliver(&nickname, replies);
+    }
+
+    /// Sends the LUSERS replies. There are no linked servers, so the local
+    /// and global counts are the same.
+    fn lusers(&self, nickname: String, connections: usize) {
+        let users = self.user_map.len();
+        let invisible = self.user_map.values().filter(|user| user.modes.contains(UserModes::INVISIBLE)).count();
+        let opers = self.user_map.values().filter(|user| user.modes.contains(UserModes::OPER)).count();
+        let replies = vec![
+            RPL_LUSERCLIENT {
+                client: nickname.clone(),
+                text: format!("There are {} users and {} invisible on 1 servers", users - invisible, invisible),
+            },
+            RPL_LUSEROP {
+                client: nickname.clone(),
+                count: opers.to_string(),
+                text: "operator(s) online".to_string(),
+            },
+            RPL_LUSERUNKNOWN {
+                client: nickname.clone(),
+                count: connections.saturating_sub(users).to_string(),
+                text: "unknown connection(s)".to_string(),
+            },
+            RPL_LUSERCHANNELS {
+                client: nickname.clone(),
+                count: self.channel_map.len().to_string(),
+                text: "channels formed".to_string(),
+            },
+            RPL_LUSERME {
+                client: nickname.clone(),
+                text: format!("I have {} clients and 0 servers", users),
+            },
+            RPL_LOCALUSERS {
+                client: nickname.clone(),
+                current: users.to_string(),
+                max: self.max_users.to_string(),
+                text: format!("Current local users {}, max {}", users, self.max_users),
+            },
+            RPL_GLOBALUSERS {
+                client: nickname.clone(),
+                current: users.to_string(),
+                max: self.max_users.to_string(),
+                text: format!("Current global users {}, max {}", users, self.max_users),
+            },
+        ];
+        self.deliver(&nickname, replies);
+    }
+
+    fn mode(&self, nickname: String, target: String) {
+        let reply = if target.starts_with(|c| isupport::CHANTYPES.contains(c)) {
+            match self.channel_map.get(&key(&target)) {
+                // Channels have no modes yet.
+                Some(_) => RPL_CHANNELMODEIS {
+                    client: nickname.clone(),
+                    channel: target,
+                    modestring: "+".to_string(),
+                },
+                None => ERR_NOSUCHCHANNEL {
+                    client: nickname.clone(),
+                    channel: target,
+                    text: "No such channel".to_string(),
+                },
+            }
+        } else if self.user_map.contains_key(&key(&target)) {
+            ERR_USERSDONTMATCH {
+                client: nickname.clone(),
+                text: "Cant change mode for other users".to_string(),
+            }
+        } else {
+            ERR_NOSUCHNICK {
+                client: nickname.clone(),
+                nick: target,
+                text: "No such nick/channel".to_string(),
+            }
+        };
+        self.deliver(&nickname, vec![reply]);
+    }
+
+    fn whowas(&self, nickname: String, target: String, count: Option<usize>) {
+        let server = CONFIG.lock().unwrap().server.clone();
+        let entries: Vec<Whowas> = self.whowas.iter().rev()
+            .filter(|entry| entry.nickname.eq_ignore_ascii_case(&target))
+            .take(count.unwrap_or(WHOWAS_LENGTH))
+            .cloned()
+            .collect();
+        let mut replies = Vec::new();
+        if entries.is_empty() {
+            replies.push(ERR_WASNOSUCHNICK {
+                client: nickname.clone(),
+                nick: target.clone(),
+                text: "There was no such nickname".to_string(),
+            });
+        }
+        for entry in entries {
+            replies.push(RPL_WHOWASUSER {
+                client: nickname.clone(),
+                nick: entry.nickname.clone(),
+                username: entry.username,
+                host: entry.host,
+                unused: "*".to_string(),
+                realname: entry.realname,
+            });
+            replies.push(RPL_WHOISSERVER {
+                client: nickname.clone(),
+                nick: entry.nickname,
+                server: server.name.clone(),
+                info: server.description.clone(),
+            });
+        }
+        replies.push(RPL_ENDOFWHOWAS {
+            client: nickname.clone(),
+            nick: target,
+            text: "End of WHOWAS".to_string(),
+        });
+        self.deliver(&nickname, replies);
+    }
+
+    /// Removes a user that quit or was dropped, telling their channels.
+    fn remove_user(&mut self, name: &str, reason: &str) {
+        let source = self.source(name);
+        self.handler_tx_map.remove(&key(name));
+        if let Some(user) = self.user_map.remove(&key(name)) {
+            self.remember(&user);
+            self.server_notice(Snomask::CONNECTS, &format!("Client exiting: {} ({}@{}) [{}]",
+                user.nickname, user.username, user.hostname, reason));
+        }
+
+        let mut recipients: Vec<String> = Vec::new();
+        for channel in self.channel_map.values_mut() {
+            if let Some(position) = channel.members.iter().position(|member| *member == name) {
+                channel.members.remove(position);
+                for member in channel.members.iter() {
+                    if !recipients.contains(member) {
+                        recipients.push(member.clone());
+                    }
+                }
+            }
+        }
+        self.channel_map.retain(|_, channel| !channel.members.is_empty());
+        self.channel_created.retain(|name, _| self.channel_map.contains_key(name));
+
+        for recipient in recipients {
+            self.send(&recipient, Message::new(
+                None,
+                Some(source.clone()),
+                Command::QUIT{
+                    reason: Some(reason.to_string()),
+                }
+            ));
+        }
+    }
+
+    /// Drops the users whose sendq overflowed. Their handlers close the
+    /// connection themselves once they see the overflow.
+    fn drop_exceeded(&mut self) {
+        loop {
+            let exceeded = std::mem::take(&mut *self.exceeded.lock().unwrap());
+            if exceeded.is_empty() {
+                return;
+            }
+            for nickname in exceeded {
+                if let Some(user) = self.user_map.get(&key(&nickname)) {
+                    let name = user.nickname.clone();
+                    self.remove_user(&name, "Max SendQ exceeded");
+                }
+            }
+        }
     }
 
     pub async fn run(&mut self) -> Result<(), ()> {
         loop {
+            self.drop_exceeded();
             tokio::select! {
                 bridge_msg_opt = self.oper_rx.recv() => {
                     if let Some(bridge_msg) = bridge_msg_opt {
                         match bridge_msg {
-                            OperMsg::AddUser{name, channel} => {
-                                self.handler_tx_map.insert(name, channel);
+                            OperMsg::AddUser{name, channel, user, accepted} => {
+                                let in_use = self.user_map.contains_key(&key(&name));
+                                let _ = accepted.send(!in_use);
+                                if in_use {
+                                    continue;
+                                }
+                                let text = format!("Client connecting: {} ({}@{}) [{}] {{{}}} [{}]",
+                                    user.nickname, user.username, user.hostname, user.ip_address, user.class, user.realname);
+                                self.handler_tx_map.insert(key(&name), channel);
+                                self.user_map.insert(key(&name), user);
+                                self.max_users = self.max_users.max(self.user_map.len());
+                                self.server_notice(Snomask::CONNECTS, &text);
+                            },
+                            OperMsg::UpdateUser{mut user} => {
+                                // A user dropped for their sendq may still
+                                // send updates until their handler notices.
+                                let Some(existing) = self.user_map.get(&key(&user.nickname)) else {
+                                    continue;
+                                };
+                                user.idle_since = existing.idle_since;
+                                let away_changed = existing.away != user.away;
+                                let nickname = user.nickname.clone();
+                                self.user_map.insert(key(&nickname), user);
+                                if away_changed {
+                                    self.away_notify(&nickname);
+                                }
+                            },
+                            OperMsg::ChangeNick{old_nickname, mut user, accepted} => {
+                                // A change of case only is never in use.
+                                let in_use = key(&user.nickname) != key(&old_nickname)
+                                    && self.user_map.contains_key(&key(&user.nickname));
+                                let known = self.user_map.contains_key(&key(&old_nickname));
+                                let _ = accepted.send(known && !in_use);
+                                if !known || in_use {
+                                    continue;
+                                }
+                                if let Some(old_user) = self.user_map.get(&key(&old_nickname)).cloned() {
+                                    user.idle_since = old_user.idle_since;
+                                    self.remember(&old_user);
+                                }
+                                self.server_notice(Snomask::NICKS, &format!("Nick change: From {} to {} [{}@{}]",
+                                    old_nickname, user.nickname, user.username, user.hostname));
+                                let nickname = user.nickname.clone();
+                                let source = Source{name: old_nickname.clone(), ..user.source()};
+                                if let Some(handler_tx) = self.handler_tx_map.remove(&key(&old_nickname)) {
+                                    self.handler_tx_map.insert(key(&nickname), handler_tx);
+                                }
+                                self.user_map.remove(&key(&old_nickname));
+                                self.user_map.insert(key(&nickname), user);
+
+                                let mut recipients: Vec<String> = vec![nickname.clone()];
+                                for channel in self.channel_map.values_mut() {
+                                    if let Some(position) = channel.members.iter().position(|member| *member == old_nickname) {
+                                        channel.members[position] = nickname.clone();
+                                        for member in channel.members.iter() {
+                                            if !recipients.contains(member) {
+                                                recipients.push(member.clone());
+                                            }
+                                        }
+                                    }
+                                }
+
+                                for recipient in recipients {
+                                    self.send(&recipient, Message::new(
+                                        None,
+                                        Some(source.clone()),
+                                        Command::NICK{
+                                            nickname: nickname.clone(),
+                                        }
+                                    ));
+                                }
+                            },
+                            OperMsg::DeleteUser{name, reason} => {
+                                self.remove_user(&name, &reason);
                             },
-                            OperMsg::DeleteUser{name} => {
-                                self.handler_tx_map.remove(&name);
+                            OperMsg::ServerNotice{snomask, text} => {
+                                self.server_notice(snomask, &text);
+                            },
+                            OperMsg::EnforceBan{ban} => {
+                                let mut killed = Vec::new();
+                                for user in self.user_map.values().filter(|user| ban.kind.disconnects() && ban.matches_user(user)) {
+                                    self.send(&user.nickname, Message::new(
+                                        None,
+                                        None,
+                                        Command::ERROR{
+                                            message: ban.disconnect_reason(),
+                                        }
+                                    ));
+                                    killed.push(format!("Disconnecting {} ({}@{}): {}",
+                                        user.nickname, user.username, user.hostname, ban.disconnect_reason()));
+                                }
+                                for text in killed {
+                                    self.server_notice(Snomask::KILLS, &text);
+                                }
                             },
                             OperMsg::JoinChannel{nickname, channel_name} => {
-                                match self.channel_map.get_mut(&channel_name) {
+                                // Members see the channel named as whoever
+                                // created it named it.
+                                let channel_name = match self.channel_map.get_mut(&key(&channel_name)) {
                                     Some(channel) => {
                                         channel.members.push(nickname.clone());
+                                        channel.name.clone()
                                     },
                                     None => {
-                                        self.channel_map.insert(channel_name.clone(),
+                                        self.channel_map.insert(key(&channel_name),
                                             Channel::new(channel_name.clone(), nickname.clone())
                                         );
+                                        self.channel_created.insert(key(&channel_name), now());
+                                        channel_name
                                     },
                                 };
 
-                                // TODO: MOTD
                                 // TODO: List of users
 
-                                match self.handler_tx_map.get(&nickname) {
-                                    Some(handler_tx) => {
-                                        let _ = handler_tx.send(Message::new(
-                                            None,
-                                            Some(Source{name: nickname, user: None, host: None}),
-                                            Command::JOIN{
-                                                channels: channel_name.clone(),
-                                                keys: None,
-                                            }
-                                        )).await;
-                                    },
-                                    None => {},
-                                };
+                                let source = self.source(&nickname);
+                                self.send(&nickname, Message::new(
+                                    None,
+                                    Some(source),
+                                    Command::JOIN{
+                                        channels: channel_name.clone(),
+                                        keys: None,
+                                    }
+                                ));
+                            },
+                            OperMsg::List{nickname, query} => {
+                                self.list(nickname, query);
+                            },
+                            OperMsg::Who{nickname, mask, options} => {
+                                self.who(nickname, mask, options);
+                            },
+                            OperMsg::Wallops{nickname, text} => {
+                                self.broadcast(&nickname, WALLOPS{text},
+                                    |user| user.modes.contains(UserModes::WALLOPS));
+                            },
+                            OperMsg::Operwall{nickname, text} => {
+                                self.broadcast(&nickname, WALLOPS{text: format!("OPERWALL - {}", text)},
+                                    |user| user.oper.is_some());
+                            },
+                            OperMsg::Announce{nickname, mask, text} => {
+                                // There are no linked servers, so a mask
+                                // either covers everyone or nobody.
+                                let server_name = CONFIG.lock().unwrap().server.name.clone();
+                                if mask::matches(&mask[1..], &server_name) {
+                                    self.broadcast(&nickname, NOTICE{targets: mask, text}, |_| true);
+                                }
+                            },
+                            OperMsg::Whois{nickname, targets} => {
+                                self.whois(nickname, targets);
+                            },
+                            OperMsg::Mode{nickname, target} => {
+                                self.mode(nickname, target);
+                            },
+                            OperMsg::Whowas{nickname, target, count} => {
+                                self.whowas(nickname, target, count);
+                            },
+                            OperMsg::Lusers{nickname, connections} => {
+                                self.lusers(nickname, connections);
+                            },
+                            OperMsg::Isupport{tokens} => {
+                                for user in self.user_map.values() {
+                                    let replies = isupport::lines(&tokens).into_iter().map(|tokens| RPL_ISUPPORT {
+                                        client: user.nickname.clone(),
+                                        tokens,
+                                        text: "are supported by this server".to_string(),
+                                    }).collect();
+                                    self.deliver(&user.nickname, replies);
+                                }
                             },
                         }
                     }
@@ -82,40 +798,51 @@ impl Bridge {
                     if let Some(message) = message_opt {
                         match &message.msg.command {
                             PRIVMSG { targets, text } => {
+                                let source = self.source(&message.user.nickname);
+                                if let Some(user) = self.user_map.get_mut(&key(&message.user.nickname)) {
+                                    user.idle_since = now();
+                                }
                                 for target in targets.split(',') {
-                                    match self.handler_tx_map.get(target) {
-                                        Some(handler_tx) => {
-                                            let _ = handler_tx.send(Message::new(
+                                    // There are no accounts yet, so only
+                                    // operators count as authenticated.
+                                    let registered_only = self.user_map.get(&key(target))
+                                        .is_some_and(|user| user.modes.contains(UserModes::REGISTERED_ONLY));
+                                    if registered_only && message.user.oper.is_none() && !target.eq_ignore_ascii_case(&message.user.nickname) {
+                                        self.reply(&message.user.nickname, ERR_NONONREG {
+                                            client: message.user.nickname.clone(),
+                                            nick: target.to_string(),
+                                            text: "You must identify to a registered nick to private message this user".to_string(),
+                                        });
+                                        continue;
+                                    }
+                                    if self.handler_tx_map.contains_key(&key(target)) {
+                                        self.send(target, Message::new(
+                                            None,
+                                            Some(source.clone()),
+                                            PRIVMSG{
+                                                targets: target.to_string(),
+                                                text: text.clone()
+                                            }
+                                        ));
+                                        let away = self.user_map.get(&key(target)).and_then(|user| user.away.clone());
+                                        if let Some(away) = away {
+                                            self.reply(&message.user.nickname, RPL_AWAY {
+                                                client: message.user.nickname.clone(),
+                                                nick: target.to_string(),
+                                                text: away,
+                                            });
+                                        }
+                                    } else if let Some(channel) = self.channel_map.get(&key(target)) {
+                                        for member in channel.members.iter() {
+                                            self.send(member, Message::new(
                                                 None,
-                                                Some(Source{name: message.user.nickname.clone(), user: None, host: None}),
+                                                Some(source.clone()),
                                                 PRIVMSG{
                                                     targets: target.to_string(),
                                                     text: text.clone()
                                                 }
-                                            )).await;
+                                            ));
                                         }
-                                        None => {
-                                            match self.channel_map.get(target) {
-                                                Some(channel) => {
-                                                    for member in channel.members.iter() {
-                                                        match self.handler_tx_map.get(member) {
-                                                            Some(handler_tx) => {
-                                                                let _ = handler_tx.send(Message::new(
-                                                                    None,
-                                                                    Some(Source{name: message.user.nickname.clone(), user: None, host: None}),
-                                                                    PRIVMSG{
-                                                                        targets: target.to_string(),
-                                                                        text: text.clone()
-                                                                    }
-                                                                )).await;
-                                                            },
-                                                            None => {},
-                                                        }
-                                                    }
-                                                },
-                                                None => {},
-                                            }
-                                        },
                                     }
                                 }
                             },
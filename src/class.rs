@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::config::{Class, CONFIG};
+use crate::mask;
+
+/// Live connection counts for every connection class, shared by all listeners.
+#[derive(Default)]
+pub struct ClassCounts {
+    clients: HashMap<String, usize>,
+    per_ip: HashMap<(String, IpAddr), usize>,
+    per_cidr: HashMap<(String, IpAddr), usize>,
+}
+
+impl ClassCounts {
+    pub fn clients(&self, class: &str) -> usize {
+        return *self.clients.get(class).unwrap_or(&0);
+    }
+
+    /// Every connection in every class.
+    pub fn total(&self) -> usize {
+        return self.clients.values().sum();
+    }
+}
+
+/// A connection's place in its class. Dropping it frees the place again.
+pub struct ClassSlot {
+    counts: Arc<Mutex<ClassCounts>>,
+    pub class: Class,
+    ip_address: IpAddr,
+}
+
+impl ClassSlot {
+    /// Picks the first class whose hosts match `ip_address` and takes a place
+    /// in it, or returns the reason the connection has to be refused.
+    pub fn assign(counts: &Arc<Mutex<ClassCounts>>, ip_address: IpAddr) -> Result<Self, String> {
+        let classes = CONFIG.lock().unwrap().class.clone();
+        let class = if classes.is_empty() {
+            Class::default()
+        } else {
+            match classes.into_iter().find(|class| class.hosts.iter().any(|host| mask::matches_address(host, ip_address))) {
+                Some(class) => class,
+                None => return Err("You are not authorized to use this server".to_string()),
+            }
+        };
+
+        let ip_key = (class.name.clone(), ip_address);
+        let cidr_key = (class.name.clone(), cidr(ip_address));
+        let mut guard = counts.lock().unwrap();
+        if class.max_clients.is_some_and(|max| guard.clients(&class.name) >= max) {
+            return Err("No more connections allowed in your connection class".to_string());
+        }
+        if class.max_per_ip.is_some_and(|max| *guard.per_ip.get(&ip_key).unwrap_or(&0) >= max) {
+            return Err("Too many connections from your host".to_string());
+        }
+        if class.max_per_cidr.is_some_and(|max| *guard.per_cidr.get(&cidr_key).unwrap_or(&0) >= max) {
+            return Err("Too many connections from your subnet".to_string());
+        }
+
+        *guard.clients.entry(class.name.clone()).or_insert(0) += 1;
+        *guard.per_ip.entry(ip_key).or_insert(0) += 1;
+        *guard.per_cidr.entry(cidr_key).or_insert(0) += 1;
+        drop(guard);
+
+        return Ok(ClassSlot { counts: counts.clone(), class, ip_address });
+    }
+
+    pub fn counts(&self) -> &Arc<Mutex<ClassCounts>> {
+        return &self.counts;
+    }
+}
+
+impl Drop for ClassSlot {
+    fn drop(&mut self) {
+        let mut guard = self.counts.lock().unwrap();
+        let name = self.class.name.clone();
+        decrement(&mut guard.clients, name.clone());
+        decrement(&mut guard.per_ip, (name.clone(), self.ip_address));
+        decrement(&mut guard.per_cidr, (name, cidr(self.ip_address)));
+    }
+}
+
+fn decrement<K: std::hash::Hash + Eq>(counts: &mut HashMap<K, usize>, key: K) {
+    if let Some(count) = counts.get_mut(&key) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(&key);
+        }
+    }
+}
+
+/// The /24 network of an IPv4 address or the /64 network of an IPv6 address.
+fn cidr(ip_address: IpAddr) -> IpAddr {
+    match ip_address {
+        IpAddr::V4(ip) => IpAddr::V4((u32::from(ip) & 0xffff_ff00).into()),
+        IpAddr::V6(ip) => IpAddr::V6((u128::from(ip) & (!0u128 << 64)).into()),
+    }
+}
@@ -0,0 +1,45 @@
+use std::net::IpAddr;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::CONFIG;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The host shown instead of `hostname` to users with mode +x.
+///
+/// Resolved hostnames keep their domain, `client.example.com` becomes
+/// `irc-1A2B3C4D.example.com`. Addresses are cloaked piece by piece from the
+/// full address down to the network prefix, so `*.<hash>.IP` still bans a
+/// whole IPv4 /16 and `*:<hash>:IP` an IPv6 /48.
+pub fn cloak(hostname: &str, ip_address: IpAddr) -> String {
+    let config = CONFIG.lock().unwrap().cloak.clone();
+    let hash = |data: String| -> String {
+        let mut mac = HmacSha256::new_from_slice(config.key.as_bytes()).expect("HMAC takes keys of any length");
+        mac.update(data.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        return format!("{:02X}{:02X}{:02X}{:02X}", digest[0], digest[1], digest[2], digest[3]);
+    };
+
+    if hostname != ip_address.to_string() {
+        return match hostname.split_once('.') {
+            Some((_, domain)) => format!("{}-{}.{}", config.prefix, hash(hostname.to_string()), domain),
+            None => format!("{}-{}", config.prefix, hash(hostname.to_string())),
+        };
+    }
+    match ip_address {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            return format!("{}.{}.{}.IP",
+                hash(format!("{}.{}.{}.{}", a, b, c, d)),
+                hash(format!("{}.{}.{}", a, b, c)),
+                hash(format!("{}.{}", a, b)));
+        },
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            let network = |count: usize| segments[..count].iter().map(|segment| format!("{:x}", segment)).collect::<Vec<_>>().join(":");
+            return format!("{}:{}:{}:IP", hash(network(8)), hash(network(4)), hash(network(3)));
+        },
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs::read_to_string;
 use std::net::IpAddr;
 use std::process::exit;
@@ -5,45 +6,346 @@ use once_cell::sync::Lazy;
 use std::sync::{Arc, Mutex};
 
 use toml;
+use irc_proto::types::Source;
 use serde_derive::Deserialize;
 
+pub const CONFIG_PATH: &str = "config.toml";
+
 pub static CONFIG: Lazy<Arc<Mutex<Config>>> = Lazy::new(|| {
-    Arc::new(Mutex::new(Config::new("config.toml")))
+    Arc::new(Mutex::new(Config::new(CONFIG_PATH)))
 });
 
 
 #[derive(Deserialize, Clone)]
 pub struct Config {
     pub server: Server,
+    #[serde(default)]
+    pub class: Vec<Class>,
+    #[serde(default)]
+    pub flood: Flood,
+    #[serde(default)]
+    pub oper: Vec<Oper>,
+    #[serde(default)]
+    pub throttle: Throttle,
+    #[serde(default)]
+    pub ident: Ident,
+    #[serde(default)]
+    pub cloak: Cloak,
+    #[serde(default)]
+    pub vhost: Vec<Vhost>,
+    #[serde(default)]
+    pub xline: Vec<BanEntry>,
+    #[serde(default)]
+    pub qline: Vec<BanEntry>,
+    #[serde(default)]
+    pub webirc: Vec<WebIrc>,
+    #[serde(default)]
+    pub websocket: Vec<WebSocket>,
+    #[serde(default)]
+    pub dnsbl: Vec<Dnsbl>,
+    #[serde(default)]
+    pub admin: Admin,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct Server {
     pub name: String,
+    #[serde(default = "default_description")]
+    pub description: String,
     pub password: String,
 
     pub address_v4: IpAddr,
     pub port: u16,
+    pub ban_file: Option<String>,
+    pub spamfilter_file: Option<String>,
+
+    #[serde(default = "default_ping_frequency")]
+    pub ping_frequency: u64,
+    #[serde(default = "default_ping_timeout")]
+    pub ping_timeout: u64,
+    #[serde(default = "default_registration_timeout")]
+    pub registration_timeout: u64,
+    #[serde(default = "default_dns_timeout")]
+    pub dns_timeout: u64,
+    /// How many WALLOPS, OPERWALL and `$mask` notices one operator may
+    /// send within `broadcast_period` seconds.
+    #[serde(default = "default_max_broadcasts")]
+    pub max_broadcasts: usize,
+    #[serde(default = "default_broadcast_period")]
+    pub broadcast_period: u64,
+    /// The message of the day sent after registration.
+    pub motd_file: Option<String>,
+
+    /// The network name advertised in RPL_ISUPPORT.
+    pub network: Option<String>,
+    #[serde(default = "default_nicklen")]
+    pub nicklen: usize,
+    #[serde(default = "default_channellen")]
+    pub channellen: usize,
+    /// How many targets one PRIVMSG or WHOIS may have.
+    #[serde(default = "default_max_targets")]
+    pub max_targets: usize,
+}
+
+impl Server {
+    /// The prefix of every message the server itself originates.
+    pub fn source(&self) -> Source {
+        return Source { name: self.name.clone(), user: None, host: None };
+    }
+}
+
+fn default_description() -> String { "IRC server".to_string() }
+fn default_ping_frequency() -> u64 { 120 }
+fn default_ping_timeout() -> u64 { 60 }
+fn default_registration_timeout() -> u64 { 30 }
+fn default_dns_timeout() -> u64 { 5 }
+fn default_max_broadcasts() -> usize { 5 }
+fn default_broadcast_period() -> u64 { 60 }
+fn default_nicklen() -> usize { 30 }
+fn default_channellen() -> usize { 50 }
+fn default_max_targets() -> usize { 4 }
+
+#[derive(Deserialize, Clone)]
+pub struct Class {
+    pub name: String,
+    pub hosts: Vec<String>,
+    pub max_clients: Option<usize>,
+    pub max_per_ip: Option<usize>,
+    pub max_per_cidr: Option<usize>,
+    /// How many bytes may wait to be sent to a client before it is
+    /// dropped with "Max SendQ exceeded".
+    #[serde(default = "default_sendq")]
+    pub sendq: usize,
+    pub ping_frequency: Option<u64>,
+    pub flood: Option<Flood>,
+    /// Replaces the server's message of the day for this class.
+    pub motd_file: Option<String>,
+}
+
+fn default_sendq() -> usize { 102400 }
+
+impl Default for Class {
+    fn default() -> Self {
+        return Class {
+            name: "default".to_string(),
+            hosts: vec!["*".to_string()],
+            max_clients: None,
+            max_per_ip: None,
+            max_per_cidr: None,
+            sendq: default_sendq(),
+            ping_frequency: None,
+            flood: None,
+            motd_file: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Flood {
+    pub burst: u32,
+    pub rate: f64,
+    pub recvq: usize,
+    pub costs: HashMap<String, u32>,
+    pub exempt_hosts: Vec<String>,
+}
+
+impl Default for Flood {
+    fn default() -> Self {
+        return Flood {
+            burst: 10,
+            rate: 1.0,
+            recvq: 8192,
+            costs: HashMap::new(),
+            exempt_hosts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Throttle {
+    pub connections: usize,
+    pub period: u64,
+    pub ban_duration: u64,
+    pub exempt: Vec<String>,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        return Throttle {
+            connections: 10,
+            period: 60,
+            ban_duration: 300,
+            exempt: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Ident {
+    pub enabled: bool,
+    pub port: u16,
+    pub timeout: u64,
+}
+
+impl Default for Ident {
+    fn default() -> Self {
+        return Ident {
+            enabled: false,
+            port: 113,
+            timeout: 3,
+        }
+    }
+}
+
+/// The key shipped in config.toml, which anyone can use to undo cloaks.
+const PLACEHOLDER_CLOAK_KEY: &str = "change-me-to-a-long-random-string";
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct Cloak {
+    /// Gives every new connection user mode +x.
+    pub enabled: bool,
+    /// Must be set to a secret of your own when `enabled` is true.
+    pub key: String,
+    pub prefix: String,
+}
+
+impl Default for Cloak {
+    fn default() -> Self {
+        return Cloak {
+            enabled: false,
+            key: String::new(),
+            prefix: "irc".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Vhost {
+    pub login: String,
+    pub password: String,
+    pub vhost: String,
+    #[serde(default = "default_vhost_hosts")]
+    pub hosts: Vec<String>,
+}
+
+fn default_vhost_hosts() -> Vec<String> { vec!["*".to_string()] }
+
+#[derive(Deserialize, Clone)]
+pub struct Oper {
+    pub name: String,
+    pub password: String,
+    pub hosts: Vec<String>,
+    /// Snomask letters set on OPER.
+    #[serde(default = "default_snomask")]
+    pub snomask: String,
+}
+
+fn default_snomask() -> String { "Sbfkr".to_string() }
+
+#[derive(Deserialize, Clone)]
+pub struct BanEntry {
+    pub mask: String,
+    pub reason: String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WebIrc {
+    pub name: String,
+    pub password: String,
+    pub hosts: Vec<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WebSocket {
+    pub address: IpAddr,
+    pub port: u16,
+    #[serde(default)]
+    pub origins: Vec<String>,
+    pub tls: Option<Tls>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Tls {
+    pub certificate: String,
+    pub key: String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Dnsbl {
+    pub zone: String,
+    pub reason: String,
+    /// What to do when the zone answers with a code missing from `replies`.
+    #[serde(default = "default_dnsbl_action")]
+    pub action: DnsblAction,
+    /// Actions for specific answers, keyed by address, e.g. `"127.0.0.3"`.
+    #[serde(default)]
+    pub replies: HashMap<String, DnsblAction>,
+}
+
+fn default_dnsbl_action() -> DnsblAction { DnsblAction::Reject }
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsblAction {
+    /// Refuses the connection.
+    Reject,
+    /// Lets the connection in and notifies opers.
+    Warn,
+    /// Ignores the answer.
+    Ignore,
+}
+
+/// What ADMIN answers with.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Admin {
+    /// Where the server is, e.g. a city and country.
+    pub location1: String,
+    /// Who runs the server.
+    pub location2: String,
+    pub email: String,
 }
 
 impl Config {
     pub fn new(path: &str) -> Self {
-        let contents  = match read_to_string(path) {
-            Ok(c) => c,
-            Err(_) => {
-                eprintln!("Could not read file \"{}\"", path);
+        match Config::load(path) {
+            Ok(config) => return config,
+            Err(err) => {
+                eprintln!("{}", err);
                 exit(1);
-            }
+            },
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = match read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => return Err(format!("Could not read file \"{}\"", path)),
         };
 
         let config: Config = match toml::from_str(&contents) {
             Ok(c) => c,
-            Err(_) => {
-                eprintln!("Could not load data from file \"{}\"", path);
-                exit(1);
-            }
+            Err(_) => return Err(format!("Could not load data from file \"{}\"", path)),
         };
 
-        return config;
+        if config.cloak.enabled && (config.cloak.key.is_empty() || config.cloak.key == PLACEHOLDER_CLOAK_KEY) {
+            return Err(format!("Cloaking is enabled in \"{}\" but cloak.key is not set", path));
+        }
+
+        return Ok(config);
     }
 }
+
+/// Re-reads `CONFIG_PATH` into `CONFIG`, keeping the running configuration
+/// when the file cannot be loaded. Settings only read at startup keep their
+/// old values until a restart: `address_v4`, `port`, `ban_file`,
+/// `spamfilter_file` and the `websocket`, `xline` and `qline` sections.
+pub fn rehash() -> Result<(), String> {
+    let config = Config::load(CONFIG_PATH)?;
+    *CONFIG.lock().unwrap() = config;
+    return Ok(());
+}
@@ -0,0 +1,25 @@
+/// Formats seconds since the Unix epoch as a UTC date, like
+/// `Monday October 19 2026 -- 14:03:07 +00:00`.
+pub fn format_time(seconds: u64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thursday", "Friday", "Saturday", "Sunday", "Monday", "Tuesday", "Wednesday"];
+    const MONTHS: [&str; 12] = ["January", "February", "March", "April", "May", "June", "July",
+        "August", "September", "October", "November", "December"];
+    let days = seconds / 86400;
+    let time = seconds % 86400;
+
+    // Days since the epoch to a civil date, after
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let shifted = days + 719468;
+    let era = shifted / 146097;
+    let day_of_era = shifted % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    return format!("{} {} {} {} -- {:02}:{:02}:{:02} +00:00",
+        WEEKDAYS[(days % 7) as usize], MONTHS[(month - 1) as usize], day, year,
+        time / 3600, time / 60 % 60, time % 60);
+}
@@ -0,0 +1,58 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+
+use tokio::task::JoinSet;
+use tokio::time::{timeout, Duration};
+
+use crate::config::{DnsblAction, CONFIG};
+use crate::resolver::Resolver;
+
+/// A DNS blacklist zone that listed a client.
+#[derive(Debug, Clone)]
+pub struct DnsblHit {
+    pub zone: String,
+    pub reason: String,
+    pub reply: Ipv4Addr,
+    pub action: DnsblAction,
+}
+
+/// Queries every configured zone for `ip_address` at once. Zones that don't
+/// answer within `dns_timeout` seconds are treated as not listing it.
+pub async fn check(resolver: Arc<dyn Resolver>, ip_address: IpAddr) -> Vec<DnsblHit> {
+    let (zones, dns_timeout) = {
+        let config = CONFIG.lock().unwrap();
+        (config.dnsbl.clone(), config.server.dns_timeout)
+    };
+
+    let mut lookups = JoinSet::new();
+    for zone in zones {
+        let resolver = resolver.clone();
+        let name = format!("{}.{}", reverse(ip_address), zone.zone);
+        lookups.spawn(async move {
+            let replies = timeout(Duration::from_secs(dns_timeout), resolver.lookup_ipv4(name)).await.unwrap_or_default();
+            let reply = *replies.first()?;
+            let action = *zone.replies.get(&reply.to_string()).unwrap_or(&zone.action);
+            if action == DnsblAction::Ignore {
+                return None;
+            }
+            return Some(DnsblHit { zone: zone.zone, reason: zone.reason, reply, action });
+        });
+    }
+
+    let mut hits = Vec::new();
+    while let Some(result) = lookups.join_next().await {
+        if let Ok(Some(hit)) = result {
+            hits.push(hit);
+        }
+    }
+    return hits;
+}
+
+/// The address in the reversed form DNSBL zones are queried with: octets for
+/// IPv4, nibbles for IPv6.
+fn reverse(ip_address: IpAddr) -> String {
+    match ip_address {
+        IpAddr::V4(ip) => ip.octets().iter().rev().map(|octet| octet.to_string()).collect::<Vec<_>>().join("."),
+        IpAddr::V6(ip) => format!("{:032x}", u128::from(ip)).chars().rev().map(|nibble| nibble.to_string()).collect::<Vec<_>>().join("."),
+    }
+}
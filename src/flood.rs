@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+
+use irc_proto::types::Message;
+use tokio::time::{Duration, Instant};
+
+use crate::config::Flood;
+
+/// Token bucket limiting how fast a connection's commands are processed.
+///
+/// Every command costs `Flood::costs[name]` tokens (1 if unlisted), the bucket
+/// holds at most `Flood::burst` tokens and refills at `Flood::rate` tokens per
+/// second. Commands that can't be paid for wait in a receive queue, and once
+/// that queue holds more than `Flood::recvq` bytes the client is flooding.
+pub struct FloodControl {
+    config: Flood,
+    tokens: f64,
+    updated: Instant,
+    queue: VecDeque<Message>,
+    queued_bytes: usize,
+}
+
+impl FloodControl {
+    pub fn new(config: Flood) -> Self {
+        let tokens = config.burst as f64;
+        return FloodControl {
+            config,
+            tokens,
+            updated: Instant::now(),
+            queue: VecDeque::new(),
+            queued_bytes: 0,
+        }
+    }
+
+    /// Queues a received message. Fails once the receive queue overflows.
+    pub fn push(&mut self, message: Message, exempt: bool) -> Result<(), ()> {
+        self.queued_bytes += message.to_bytes().len();
+        self.queue.push_back(message);
+        if !exempt && self.queued_bytes > self.config.recvq {
+            return Err(());
+        }
+        return Ok(());
+    }
+
+    /// Takes the next queued message if the bucket can pay for it.
+    pub fn pop(&mut self, exempt: bool) -> Option<Message> {
+        let cost = self.cost(self.queue.front()?);
+        if !exempt {
+            self.refill();
+            if self.tokens < cost {
+                return None;
+            }
+            self.tokens -= cost;
+        }
+
+        let message = self.queue.pop_front()?;
+        self.queued_bytes -= message.to_bytes().len();
+        return Some(message);
+    }
+
+    /// When the message at the front of the queue becomes affordable, or
+    /// `None` if the queue is empty or the bucket never refills far enough,
+    /// as with a `rate` of 0.
+    pub fn ready_at(&self) -> Option<Instant> {
+        let cost = self.cost(self.queue.front()?);
+        let missing = (cost - self.tokens).max(0.0);
+        if missing == 0.0 {
+            return Some(self.updated);
+        }
+        let wait = Duration::try_from_secs_f64(missing / self.config.rate).ok()?;
+        return self.updated.checked_add(wait);
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned = now.duration_since(self.updated).as_secs_f64() * self.config.rate;
+        self.tokens = (self.tokens + earned).min(self.config.burst as f64);
+        self.updated = now;
+    }
+
+    fn cost(&self, message: &Message) -> f64 {
+        let name = command_name(message);
+        let cost = *self.config.costs.get(&name).unwrap_or(&1) as f64;
+        return cost.min(self.config.burst as f64);
+    }
+}
+
+/// The command word of a message, as it appears on the wire.
+pub fn command_name(message: &Message) -> String {
+    return message.to_bytes()
+        .split_whitespace()
+        .find(|word| !word.starts_with(['@', ':']))
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+}
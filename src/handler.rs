@@ -1,31 +1,88 @@
-use irc_proto::types::{Command::{self, *}, Message, Source};
-use irc_proto::connection::Connection;
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+
+use irc_proto::types::{Command::{self, *}, Message};
 use log::info;
-use tokio::sync::{broadcast, mpsc};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration, Instant};
+
+use crate::{ban::{self, Ban, BanKind, BANS}, bridge::{CommMsg, OperMsg}, class::ClassSlot, cloak, config::{self, DnsblAction, CONFIG, CONFIG_PATH}, date::format_time, dnsbl::{self, DnsblHit}, flood::FloodControl, ident, isupport::{self, CHANTYPES}, mask, motd::MOTD, spamfilter::{FilterAction, FilterTarget, SpamFilter, SPAMFILTERS}, server::{STARTED, VERSION}, snomask::Snomask, throttle::{Throttled, Throttler}, resolver::{self, Hostname, Resolver}, sendq::{self, SendQReceiver}, transport::Transport, user::{RegistrationFlags, User, UserModes, AWAYLEN, CAPABILITIES, USER_MODES}};
 
-use crate::{bridge::{CommMsg, OperMsg}, config::CONFIG, user::{RegistrationFlags, User}};
 
+/// How long a client dropped for its sendq gets to take the ERROR.
+const SENDQ_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Handler {
-    pub connection: Connection,
-    handler_rx: mpsc::Receiver<Message>,
+    pub connection: Transport,
+    class: ClassSlot,
+    throttle: Arc<Mutex<Throttler>>,
+    resolver: Arc<dyn Resolver>,
+    dnsbl: Option<JoinHandle<Vec<DnsblHit>>>,
+    hostname_lookup: Option<JoinHandle<Hostname>>,
+    ident_lookup: Option<JoinHandle<Option<String>>>,
+    handler_rx: SendQReceiver,
     oper_tx: mpsc::Sender<OperMsg>,
     comm_tx: mpsc::Sender<CommMsg>,
     user: User,
     shutdown: broadcast::Receiver<()>,
+    connected: Instant,
+    last_activity: Instant,
+    ping_sent: bool,
+    flood: FloodControl,
+    /// When recent broadcasts were sent, for `max_broadcasts`.
+    broadcasts: VecDeque<Instant>,
     _running: bool,
 }
 
 impl Handler {
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        connection: Connection,
+        connection: Transport,
+        local_address: SocketAddr,
+        class: ClassSlot,
+        throttle: Arc<Mutex<Throttler>>,
+        resolver: Arc<dyn Resolver>,
         oper_tx: mpsc::Sender<OperMsg>,
         comm_tx: mpsc::Sender<CommMsg>,
         shutdown: broadcast::Receiver<()>,
     ) -> Self {
-        let (_handler_tx, handler_rx) = mpsc::channel(1);
-        return Handler { connection, handler_rx, oper_tx, comm_tx, user: User::new(), shutdown, _running: true }
+        let (_handler_tx, handler_rx) = sendq::channel(0);
+        let mut user = User::new(connection.address().ip());
+        user.secure = connection.is_secure();
+        user.class = class.class.name.clone();
+        let now = Instant::now();
+        let flood = FloodControl::new(match &class.class.flood {
+            Some(flood) => flood.clone(),
+            None => CONFIG.lock().unwrap().flood.clone(),
+        });
+        let dnsbl = Some(tokio::spawn(dnsbl::check(resolver.clone(), user.ip_address)));
+        let hostname_lookup = Some(tokio::spawn(resolver::lookup_hostname(resolver.clone(), user.ip_address)));
+        let ident_lookup = CONFIG.lock().unwrap().ident.enabled
+            .then(|| tokio::spawn(ident::lookup(local_address, connection.address())));
+        return Handler {
+            connection,
+            class,
+            throttle,
+            resolver,
+            dnsbl,
+            hostname_lookup,
+            ident_lookup,
+            handler_rx,
+            oper_tx,
+            comm_tx,
+            user,
+            shutdown,
+            connected: now,
+            last_activity: now,
+            ping_sent: false,
+            flood,
+            broadcasts: VecDeque::new(),
+            _running: true,
+        }
     }
 
     async fn shutdown(&mut self) {
@@ -33,15 +90,682 @@ impl Handler {
 
     }
 
+    async fn reply(&mut self, command: Command) {
+        let source = CONFIG.lock().unwrap().server.source();
+        let _ = self.connection.write(Message {
+            tags: None,
+            source: Some(source),
+            command,
+        }).await;
+    }
+
+    async fn close_link(&mut self, reason: &str) {
+        self.connection.close_link(reason).await;
+        self.quit(reason).await;
+        self._running = false;
+    }
+
+    /// Drops a client whose sendq overflowed. The bridge has already
+    /// removed it, and as the client isn't reading, the ERROR only gets
+    /// `SENDQ_CLOSE_TIMEOUT` to go out.
+    async fn sendq_exceeded(&mut self) {
+        let _ = time::timeout(SENDQ_CLOSE_TIMEOUT, self.connection.close_link("Max SendQ exceeded")).await;
+        self._running = false;
+    }
+
+    async fn quit(&mut self, reason: &str) {
+        if self.user.is_registered() {
+            let _ = self.oper_tx.send(OperMsg::DeleteUser{
+                name: self.user.nickname.clone(),
+                reason: reason.to_string(),
+            }).await;
+        }
+    }
+
+    async fn notice(&mut self, text: String) {
+        let source = CONFIG.lock().unwrap().server.source();
+        let _ = self.connection.write(Message {
+            tags: None,
+            source: Some(source),
+            command: Command::NOTICE {
+                targets: if self.user.nickname.is_empty() { "*".to_string() } else { self.user.nickname.clone() },
+                text,
+            },
+        }).await;
+    }
+
+    /// Waits for the reverse lookup started on connect and takes its result
+    /// as the hostname.
+    async fn finish_hostname_lookup(&mut self) {
+        let Some(lookup) = self.hostname_lookup.take() else {
+            return;
+        };
+        match lookup.await.unwrap_or(Hostname::NotFound) {
+            Hostname::Found(hostname) if is_valid_hostname(&hostname) => {
+                self.user.hostname = hostname;
+                self.notice("*** Found your hostname".to_string()).await;
+            },
+            Hostname::Mismatch => {
+                self.notice("*** Your forward and reverse DNS do not match, ignoring hostname".to_string()).await;
+            },
+            _ => {
+                self.notice("*** Couldn't look up your hostname".to_string()).await;
+            },
+        }
+    }
+
+    /// Waits for the ident lookup started on connect. Usernames that ident
+    /// couldn't confirm get a `~` prefix.
+    async fn finish_ident_lookup(&mut self) {
+        let Some(lookup) = self.ident_lookup.take() else {
+            return;
+        };
+        match lookup.await.unwrap_or_default() {
+            Some(userid) => {
+                self.user.username = userid;
+                self.notice("*** Got Ident response".to_string()).await;
+            },
+            None => {
+                self.user.username = format!("~{}", self.user.username);
+                self.notice("*** No Ident response".to_string()).await;
+            },
+        }
+    }
+
+    async fn register(&mut self) {
+        if self.user.is_registered() {
+            self.finish_hostname_lookup().await;
+            self.finish_ident_lookup().await;
+            let cloak_enabled = CONFIG.lock().unwrap().cloak.enabled;
+            if cloak_enabled {
+                self.user.cloaked_host = cloak::cloak(&self.user.hostname, self.user.ip_address);
+                self.user.modes.insert(UserModes::CLOAKED);
+            }
+            let ban = BANS.lock().unwrap().find_user(&self.user);
+            if let Some(ban) = ban {
+                let _ = self.oper_tx.send(OperMsg::ServerNotice{
+                    snomask: Snomask::REJECTS,
+                    text: format!("Rejected {} ({}@{}) [{}]: {}", self.user.nickname, self.user.username,
+                        self.user.hostname, self.user.ip_address, ban.disconnect_reason()),
+                }).await;
+                self.close_link(&ban.disconnect_reason()).await;
+                return;
+            }
+
+            // Registration waits for the DNSBL lookups started on connect.
+            let hits = match self.dnsbl.take() {
+                Some(lookup) => lookup.await.unwrap_or_default(),
+                None => Vec::new(),
+            };
+            for hit in hits {
+                let _ = self.oper_tx.send(OperMsg::ServerNotice{
+                    snomask: Snomask::REJECTS,
+                    text: format!("{} [{}] is listed in {} ({})",
+                        self.user.nickname, self.user.ip_address, hit.zone, hit.reply),
+                }).await;
+                if hit.action == DnsblAction::Reject {
+                    self.close_link(&format!("DNSBL: {}", hit.reason)).await;
+                    return;
+                }
+            }
+
+            self.user.signon = ban::now();
+            self.user.idle_since = self.user.signon;
+            let (handler_tx, handler_rx) = sendq::channel(self.class.class.sendq);
+            self.handler_rx = handler_rx;
+            let (accepted_tx, accepted_rx) = oneshot::channel();
+            let _ = self.oper_tx.send(OperMsg::AddUser{
+                name: self.user.nickname.clone(),
+                channel: handler_tx,
+                user: self.user.clone(),
+                accepted: accepted_tx,
+            }).await;
+            if accepted_rx.await != Ok(true) {
+                // Registration finishes once a free nickname is given.
+                let nickname = std::mem::take(&mut self.user.nickname);
+                self.reply(Command::ERR_NICKNAMEINUSE {
+                    client: "*".to_string(),
+                    nick: nickname,
+                    text: "Nickname is already in use".to_string(),
+                }).await;
+                self.user.register_state.remove(RegistrationFlags::NICK);
+                return;
+            }
+            self.welcome().await;
+            self.motd().await;
+            if self.user.modes.contains(UserModes::CLOAKED) {
+                self.host_hidden().await;
+            }
+        }
+    }
+
+    async fn cap_reply(&mut self, subcommand: &str, capabilities: String) {
+        let client = if self.user.nickname.is_empty() { "*".to_string() } else { self.user.nickname.clone() };
+        self.reply(Command::CAP {
+            params: vec![client, subcommand.to_string(), capabilities],
+        }).await;
+    }
+
+    /// Sends RPL_WELCOME through RPL_MYINFO once registration completes.
+    async fn welcome(&mut self) {
+        let server_name = CONFIG.lock().unwrap().server.name.clone();
+        let nickname = self.user.nickname.clone();
+        self.reply(Command::RPL_WELCOME {
+            client: nickname.clone(),
+            text: format!("Welcome to the Internet Relay Network {}!{}@{}",
+                nickname, self.user.username, self.user.visible_host()),
+        }).await;
+        self.reply(Command::RPL_YOURHOST {
+            client: nickname.clone(),
+            text: format!("Your host is {}, running version {}", server_name, VERSION),
+        }).await;
+        self.reply(Command::RPL_CREATED {
+            client: nickname.clone(),
+            text: format!("This server was created {}", format_time(*STARTED)),
+        }).await;
+        self.reply(Command::RPL_MYINFO {
+            client: nickname,
+            server: server_name,
+            version: VERSION.to_string(),
+            user_modes: USER_MODES.iter().map(|(letter, _)| *letter).collect(),
+            channel_modes: String::new(),
+        }).await;
+        self.isupport().await;
+    }
+
+    async fn motd(&mut self) {
+        let server_name = CONFIG.lock().unwrap().server.name.clone();
+        let lines = MOTD.lock().unwrap().lines(&self.class.class.name);
+        let Some(lines) = lines else {
+            self.reply(Command::ERR_NOMOTD {
+                client: self.user.nickname.clone(),
+                text: "MOTD File is missing".to_string(),
+            }).await;
+            return;
+        };
+        self.reply(Command::RPL_MOTDSTART {
+            client: self.user.nickname.clone(),
+            text: format!("- {} Message of the day - ", server_name),
+        }).await;
+        for line in lines {
+            self.reply(Command::RPL_MOTD {
+                client: self.user.nickname.clone(),
+                text: format!("- {}", line),
+            }).await;
+        }
+        self.reply(Command::RPL_ENDOFMOTD {
+            client: self.user.nickname.clone(),
+            text: "End of /MOTD command.".to_string(),
+        }).await;
+    }
+
+    async fn isupport(&mut self) {
+        for tokens in isupport::lines(&isupport::tokens()) {
+            self.reply(Command::RPL_ISUPPORT {
+                client: self.user.nickname.clone(),
+                tokens,
+                text: "are supported by this server".to_string(),
+            }).await;
+        }
+    }
+
+    /// Sends ERR_NOSUCHSERVER and returns true when `server` names a server
+    /// other than this one. There are no linked servers to forward queries to.
+    async fn is_other_server(&mut self, server: Option<String>) -> bool {
+        let server_name = CONFIG.lock().unwrap().server.name.clone();
+        match server {
+            Some(server) if !mask::matches(&server, &server_name) => {
+                self.reply(Command::ERR_NOSUCHSERVER {
+                    client: self.user.nickname.clone(),
+                    server,
+                    text: "No such server".to_string(),
+                }).await;
+                return true;
+            },
+            _ => return false,
+        }
+    }
+
+    async fn host_hidden(&mut self) {
+        self.reply(Command::RPL_HOSTHIDDEN {
+            client: self.user.nickname.clone(),
+            host: self.user.visible_host().to_string(),
+            text: "is now your displayed host".to_string(),
+        }).await;
+    }
+
+    /// The snomask an operator gets from their oper block.
+    fn default_snomask(&self) -> Snomask {
+        let config = CONFIG.lock().unwrap();
+        return config.oper.iter()
+            .find(|block| self.user.oper.as_ref() == Some(&block.name))
+            .map_or(Snomask::empty(), |block| Snomask::empty().apply(&block.snomask));
+    }
+
+    /// Applies a user mode string to ourselves and echoes the modes that
+    /// actually changed. `+s` takes a snomask change such as `+cn-k` from
+    /// `args`.
+    async fn set_user_modes(&mut self, modestring: &str, args: Vec<String>) {
+        let cloak_enabled = CONFIG.lock().unwrap().cloak.enabled;
+        let mut args = args.into_iter();
+        let mut adding = true;
+        let mut changed = String::new();
+        let mut snomask_changed = false;
+        let mut unknown = false;
+        let mut unknown_snomask = None;
+        for letter in modestring.chars() {
+            if letter == '+' || letter == '-' {
+                adding = letter == '+';
+                continue;
+            }
+            let mode = match USER_MODES.iter().find(|(known, _)| *known == letter) {
+                Some((_, mode)) if *mode == UserModes::CLOAKED && !cloak_enabled => None,
+                Some((_, mode)) => Some(*mode),
+                None => None,
+            };
+            let Some(mode) = mode else {
+                unknown = true;
+                continue;
+            };
+            if mode == UserModes::SNOTICE {
+                let change = if adding { args.next() } else { None };
+                if let Some(letter) = change.as_deref().and_then(Snomask::unknown_letter) {
+                    unknown_snomask = Some(letter);
+                    continue;
+                }
+                let snomask = match (self.user.oper.is_some(), adding, change) {
+                    (false, true, _) => continue,
+                    (_, false, _) => Snomask::empty(),
+                    (true, true, Some(change)) => self.user.snomask.apply(&change),
+                    (true, true, None) if self.user.snomask.is_empty() => self.default_snomask(),
+                    (true, true, None) => self.user.snomask,
+                };
+                snomask_changed |= snomask != self.user.snomask;
+                self.user.snomask = snomask;
+                if self.user.modes.contains(UserModes::SNOTICE) == snomask.is_empty() {
+                    self.user.modes.toggle(UserModes::SNOTICE);
+                    changed.push(if snomask.is_empty() { '-' } else { '+' });
+                    changed.push(letter);
+                }
+                continue;
+            }
+            // Operator status only comes from OPER.
+            if (mode == UserModes::OPER && adding) || self.user.modes.contains(mode) == adding {
+                continue;
+            }
+            if mode == UserModes::OPER {
+                info!("{} is no longer an operator", self.user.nickname);
+                self.user.oper = None;
+            }
+            if mode == UserModes::CLOAKED && self.user.cloaked_host.is_empty() {
+                self.user.cloaked_host = cloak::cloak(&self.user.hostname, self.user.ip_address);
+            }
+            self.user.modes.set(mode, adding);
+            changed.push(if adding { '+' } else { '-' });
+            changed.push(letter);
+            // Server notices are for operators only.
+            if mode == UserModes::OPER && self.user.modes.contains(UserModes::SNOTICE) {
+                self.user.modes.remove(UserModes::SNOTICE);
+                self.user.snomask = Snomask::empty();
+                snomask_changed = true;
+                changed.push_str("-s");
+            }
+        }
+        if unknown {
+            self.reply(Command::ERR_UMODEUNKNOWNFLAG {
+                client: self.user.nickname.clone(),
+                text: "Unknown MODE flag".to_string(),
+            }).await;
+        }
+        if let Some(letter) = unknown_snomask {
+            self.reply(Command::ERR_UMODEUNKNOWNFLAG {
+                client: self.user.nickname.clone(),
+                text: format!("Unknown server notice mask {}", letter),
+            }).await;
+        }
+        if changed.is_empty() && !snomask_changed {
+            return;
+        }
+
+        let _ = self.oper_tx.send(OperMsg::UpdateUser{
+            user: self.user.clone(),
+        }).await;
+        if !changed.is_empty() {
+            let _ = self.connection.write(Message {
+                tags: None,
+                source: Some(self.user.source()),
+                command: Command::MODE {
+                    target: self.user.nickname.clone(),
+                    modestring: Some(changed.clone()),
+                    args: Vec::new(),
+                },
+            }).await;
+        }
+        if changed.contains('x') {
+            self.host_hidden().await;
+        }
+        if snomask_changed && !self.user.snomask.is_empty() {
+            self.reply(Command::RPL_SNOMASK {
+                client: self.user.nickname.clone(),
+                snomask: format!("+{}", self.user.snomask.letters()),
+                text: "Server notice mask".to_string(),
+            }).await;
+        }
+    }
+
+    /// Unregistered connections get `registration_timeout` seconds to finish
+    /// registering. Registered ones are sent a PING after `ping_frequency`
+    /// idle seconds and dropped if nothing arrives within `ping_timeout`.
+    fn deadline(&self) -> Instant {
+        let config = CONFIG.lock().unwrap();
+        if !self.user.is_registered() {
+            return self.connected + Duration::from_secs(config.server.registration_timeout);
+        }
+        let idle = Duration::from_secs(self.class.class.ping_frequency.unwrap_or(config.server.ping_frequency));
+        if self.ping_sent {
+            return self.last_activity + idle + Duration::from_secs(config.server.ping_timeout);
+        }
+        return self.last_activity + idle;
+    }
+
+    fn is_flood_exempt(&self) -> bool {
+        if self.user.oper.is_some() {
+            return true;
+        }
+        let config = CONFIG.lock().unwrap();
+        return config.flood.exempt_hosts.iter().any(|host| self.user.matches_host(host));
+    }
+
+    async fn process_queue(&mut self) {
+        let exempt = self.is_flood_exempt();
+        while self._running {
+            match self.flood.pop(exempt) {
+                Some(msg) => self.process_message(msg).await,
+                None => break,
+            }
+        }
+    }
+
+    /// Passes an operator's WALLOPS, OPERWALL or `$mask` notice on to the
+    /// bridge, unless they have sent too many of them recently. A NOTICE is
+    /// never answered, so a refused one is dropped without a reply.
+    async fn broadcast(&mut self, command: &str, broadcast: OperMsg) {
+        let quiet = command == "NOTICE";
+        if !self.user.is_registered() || (quiet && self.user.oper.is_none()) {
+            return;
+        }
+        if self.user.oper.is_none() {
+            self.reply(Command::ERR_NOPRIVILEGES {
+                client: self.user.nickname.clone(),
+                text: "Permission Denied- You're not an IRC operator".to_string(),
+            }).await;
+            return;
+        }
+        let (max_broadcasts, period) = {
+            let config = CONFIG.lock().unwrap();
+            (config.server.max_broadcasts, Duration::from_secs(config.server.broadcast_period))
+        };
+        let now = Instant::now();
+        while self.broadcasts.front().is_some_and(|sent| now.duration_since(*sent) >= period) {
+            self.broadcasts.pop_front();
+        }
+        if self.broadcasts.len() >= max_broadcasts {
+            if quiet {
+                return;
+            }
+            self.reply(Command::RPL_TRYAGAIN {
+                client: self.user.nickname.clone(),
+                command: command.to_string(),
+                text: "Please wait a while and try again.".to_string(),
+            }).await;
+            return;
+        }
+        self.broadcasts.push_back(now);
+        let _ = self.oper_tx.send(broadcast).await;
+    }
+
+    async fn add_ban(&mut self, kind: BanKind, duration: Option<String>, mask: String, reason: String) {
+        if !self.user.is_registered() {
+            return;
+        }
+        if self.user.oper.is_none() {
+            self.reply(Command::ERR_NOPRIVILEGES {
+                client: self.user.nickname.clone(),
+                text: "Permission Denied- You're not an IRC operator".to_string(),
+            }).await;
+            return;
+        }
+
+        let minutes = match duration.as_deref().map(str::parse::<u64>) {
+            Some(Ok(minutes)) => minutes,
+            Some(Err(_)) => {
+                self.notice(format!("Invalid duration for {}", kind.name())).await;
+                return;
+            },
+            None => 0,
+        };
+        let mask = if matches!(kind, BanKind::Kline | BanKind::Gline | BanKind::Shun) && !mask.contains('@') {
+            format!("*@{}", mask)
+        } else {
+            mask
+        };
+        if matches!(kind, BanKind::Xline | BanKind::Qline) && !mask::is_valid_pattern(&mask) {
+            self.notice(format!("Invalid mask for {}: {}", kind.name(), mask)).await;
+            return;
+        }
+        let ban = Ban::new(kind, mask, reason, self.user.nickname.clone(), (minutes > 0).then_some(minutes.saturating_mul(60)));
+        let expiry = match minutes {
+            0 => "permanent".to_string(),
+            minutes => format!("{} minutes", minutes),
+        };
+        let text = format!("{} added {} {} for [{}]: {}",
+            self.user.nickname, expiry, kind.name(), ban.mask, ban.reason);
+        BANS.lock().unwrap().add(ban.clone());
+
+        self.notice(format!("Added {} {} for [{}]", expiry, kind.name(), ban.mask)).await;
+        let _ = self.oper_tx.send(OperMsg::ServerNotice{ snomask: Snomask::BANS, text }).await;
+        if kind.disconnects() {
+            let _ = self.oper_tx.send(OperMsg::EnforceBan{ ban }).await;
+        }
+    }
+
+    async fn remove_ban(&mut self, kind: BanKind, mask: String) {
+        if !self.user.is_registered() {
+            return;
+        }
+        if self.user.oper.is_none() {
+            self.reply(Command::ERR_NOPRIVILEGES {
+                client: self.user.nickname.clone(),
+                text: "Permission Denied- You're not an IRC operator".to_string(),
+            }).await;
+            return;
+        }
+
+        let mask = if matches!(kind, BanKind::Kline | BanKind::Gline | BanKind::Shun) && !mask.contains('@') {
+            format!("*@{}", mask)
+        } else {
+            mask
+        };
+        let removed = BANS.lock().unwrap().remove(kind, &mask);
+        if removed {
+            self.notice(format!("{} for [{}] is removed", kind.name(), mask)).await;
+            let _ = self.oper_tx.send(OperMsg::ServerNotice{
+                snomask: Snomask::BANS,
+                text: format!("{} has removed the {} for: [{}]", self.user.nickname, kind.name(), mask),
+            }).await;
+        } else {
+            self.notice(format!("No {} for [{}] found", kind.name(), mask)).await;
+        }
+    }
+
+    async fn add_spamfilter(&mut self, targets: String, action: String, duration: String, mask: String, reason: String) {
+        if !self.user.is_registered() {
+            return;
+        }
+        if self.user.oper.is_none() {
+            self.reply(Command::ERR_NOPRIVILEGES {
+                client: self.user.nickname.clone(),
+                text: "Permission Denied- You're not an IRC operator".to_string(),
+            }).await;
+            return;
+        }
+
+        let Some(targets) = FilterTarget::parse(&targets) else {
+            self.notice(format!("Invalid spamfilter targets: {}", targets)).await;
+            return;
+        };
+        let Some(action) = FilterAction::parse(&action) else {
+            self.notice(format!("Invalid spamfilter action: {}", action)).await;
+            return;
+        };
+        let Ok(minutes) = duration.parse::<u64>() else {
+            self.notice("Invalid duration for spamfilter".to_string()).await;
+            return;
+        };
+        if !mask::is_valid_pattern(&mask) {
+            self.notice(format!("Invalid mask for spamfilter: {}", mask)).await;
+            return;
+        }
+        let filter = SpamFilter::new(mask, targets, action,
+            (minutes > 0).then_some(minutes.saturating_mul(60)), reason, self.user.nickname.clone());
+        let text = format!("{} added spamfilter for [{}] ({} {}): {}",
+            self.user.nickname, filter.mask, filter.target_letters(), action.name(), filter.reason);
+        self.notice(format!("Added spamfilter for [{}]", filter.mask)).await;
+        SPAMFILTERS.lock().unwrap().add(filter);
+        let _ = self.oper_tx.send(OperMsg::ServerNotice{ snomask: Snomask::SPAMFILTER, text }).await;
+    }
+
+    async fn remove_spamfilter(&mut self, mask: String) {
+        if !self.user.is_registered() {
+            return;
+        }
+        if self.user.oper.is_none() {
+            self.reply(Command::ERR_NOPRIVILEGES {
+                client: self.user.nickname.clone(),
+                text: "Permission Denied- You're not an IRC operator".to_string(),
+            }).await;
+            return;
+        }
+
+        let removed = SPAMFILTERS.lock().unwrap().remove(&mask);
+        if removed {
+            self.notice(format!("Spamfilter for [{}] is removed", mask)).await;
+            let _ = self.oper_tx.send(OperMsg::ServerNotice{
+                snomask: Snomask::SPAMFILTER,
+                text: format!("{} has removed the spamfilter for: [{}]", self.user.nickname, mask),
+            }).await;
+        } else {
+            self.notice(format!("No spamfilter for [{}] found", mask)).await;
+        }
+    }
+
+    /// Runs the text carried by `msg` through the spam filters. Returns the
+    /// message to process, which has its reason stripped if it is a PART or
+    /// QUIT that hit a filter, or `None` when it has to be dropped.
+    async fn spam_filter(&mut self, msg: Message) -> Option<Message> {
+        if !self.user.is_registered() || self.user.oper.is_some() {
+            return Some(msg);
+        }
+        let checks = match &msg.command {
+            PRIVMSG { targets, text } | NOTICE { targets, text } => targets.split(',')
+                .map(|target| {
+                    let kind = if target.starts_with('#') { FilterTarget::Channel } else { FilterTarget::Private };
+                    (kind, target.to_string(), text.clone())
+                })
+                .collect(),
+            PART { channels, reason: Some(reason) } => vec![(FilterTarget::Part, channels.clone(), reason.clone())],
+            QUIT { reason: Some(reason) } => vec![(FilterTarget::Quit, "QUIT".to_string(), reason.clone())],
+            TOPIC { channel, topic: Some(topic) } => vec![(FilterTarget::Topic, channel.clone(), topic.clone())],
+            _ => return Some(msg),
+        };
+
+        for (kind, target, text) in checks {
+            let filter = SPAMFILTERS.lock().unwrap().check(kind, &text);
+            let Some(filter) = filter else {
+                continue;
+            };
+            let _ = self.oper_tx.send(OperMsg::ServerNotice{
+                snomask: Snomask::SPAMFILTER,
+                text: format!("Spamfilter [{}] matched by {} ({} to {}): {}, action: {}",
+                    filter.mask, self.user.nickname, kind.letter(), target, text, filter.action.name()),
+            }).await;
+
+            match filter.action {
+                FilterAction::Warn => continue,
+                FilterAction::Block => {
+                    self.notice(format!("Message to {} blocked: {}", target, filter.reason)).await;
+                },
+                FilterAction::Kill => {
+                    self.close_link(&format!("Spamfilter: {}", filter.reason)).await;
+                    return None;
+                },
+                FilterAction::Gline | FilterAction::Shun => {
+                    let server_name = CONFIG.lock().unwrap().server.name.clone();
+                    let kind = if filter.action == FilterAction::Gline { BanKind::Gline } else { BanKind::Shun };
+                    let ban = Ban::new(kind, format!("*@{}", self.user.ip_address), filter.reason, server_name, filter.duration);
+                    BANS.lock().unwrap().add(ban.clone());
+                    if kind.disconnects() {
+                        let _ = self.oper_tx.send(OperMsg::EnforceBan{ ban }).await;
+                    }
+                },
+            }
+            return match msg.command {
+                PART { channels, reason: _ } => Some(Message { command: PART { channels, reason: None }, ..msg }),
+                QUIT { reason: _ } => Some(Message { command: QUIT { reason: None }, ..msg }),
+                _ => None,
+            };
+        }
+        return Some(msg);
+    }
+
+    async fn timeout(&mut self) {
+        if !self.user.is_registered() {
+            self.close_link("Registration timed out").await;
+        } else if !self.ping_sent {
+            let server_name = CONFIG.lock().unwrap().server.name.clone();
+            let _ = self.connection.write(Message {
+                tags: None,
+                source: None,
+                command: Command::PING {
+                    token: server_name,
+                },
+            }).await;
+            self.ping_sent = true;
+        } else {
+            let reason = format!("Ping timeout: {} seconds", self.last_activity.elapsed().as_secs());
+            self.close_link(&reason).await;
+        }
+    }
+
     pub async fn run(&mut self) -> Result<(), ()> {
+        self.notice("*** Looking up your hostname...".to_string()).await;
+        if self.ident_lookup.is_some() {
+            self.notice("*** Checking Ident".to_string()).await;
+        }
         while self._running {
+            let deadline = self.deadline();
+            let flood_ready = self.flood.ready_at();
+            let overflow = self.handler_rx.overflow();
             tokio::select! {
                 client_message = self.connection.read() => {
                     info!("client message");
                     match client_message {
-                        Ok(msg) => self.process_message(msg).await,
+                        Ok(msg) => {
+                            self.last_activity = Instant::now();
+                            self.ping_sent = false;
+                            if self.flood.push(msg, self.is_flood_exempt()).is_err() {
+                                let _ = self.oper_tx.send(OperMsg::ServerNotice{
+                                    snomask: Snomask::FLOOD,
+                                    text: format!("Excess flood from {} [{}]", self.user.nickname, self.user.ip_address),
+                                }).await;
+                                self.close_link("Excess Flood").await;
+                            } else {
+                                self.process_queue().await;
+                            }
+                        },
                         Err(_) => {
                             self.shutdown().await;
+                            self.quit("Connection closed").await;
                             return Err(())
                         },
                     }
@@ -51,8 +775,17 @@ impl Handler {
                     match server_message {
                         Some(message) => {
                             match message.command {
+                                ERROR { message } => {
+                                    self.close_link(&message).await;
+                                },
                                 _ => {
-                                    let _ = self.connection.write(message).await;
+                                    // A client that stops reading must not
+                                    // keep the handler in write once its
+                                    // sendq has overflowed.
+                                    tokio::select! {
+                                        _ = self.connection.write(message) => {},
+                                        _ = overflow.exceeded() => self.sendq_exceeded().await,
+                                    }
                                 },
                             }
                         },
@@ -60,6 +793,18 @@ impl Handler {
                     }
                 }
 
+                _ = overflow.exceeded() => {
+                    self.sendq_exceeded().await;
+                },
+
+                _ = time::sleep_until(flood_ready.unwrap_or(deadline)), if flood_ready.is_some() => {
+                    self.process_queue().await;
+                },
+
+                _ = time::sleep_until(deadline) => {
+                    self.timeout().await;
+                },
+
                 _ = self.shutdown.recv() => {
                     info!("shutdown signal");
                     self.shutdown().await;
@@ -70,17 +815,32 @@ impl Handler {
         return Ok(());
     }
 
+    /// Shunned users stay connected, but everything they send apart from
+    /// PING, PONG, PART and QUIT is silently dropped.
+    fn is_shunned(&self, msg: &Message) -> bool {
+        if !self.user.is_registered() || self.user.oper.is_some() {
+            return false;
+        }
+        if matches!(msg.command, PING { .. } | PONG { .. } | PART { .. } | QUIT { .. }) {
+            return false;
+        }
+        return BANS.lock().unwrap().find_shun(&self.user).is_some();
+    }
+
     async fn process_message(&mut self, msg: Message) {
+        if self.is_shunned(&msg) {
+            return;
+        }
+        let Some(msg) = self.spam_filter(msg).await else {
+            return;
+        };
         match msg.command {
             PING { token } => {
+                let source = CONFIG.lock().unwrap().server.source();
                 let _ = self.connection.write(
                     Message {
                         tags: None,
-                        source: Some(Source{
-                                name: "server1".to_string(),
-                                user: None,
-                                host: None
-                        }),
+                        source: Some(source),
                         command: Command::PONG{
                             server: None, token
                         },
@@ -88,69 +848,279 @@ impl Handler {
                 ).await;
             },
             PASS { password } => {
-                if self.user.register_state.is_empty() {
+                if !self.user.register_state.intersects(RegistrationFlags::PASS | RegistrationFlags::NICK | RegistrationFlags::USER) {
                     let server_passwd = CONFIG.lock().unwrap().server.password.clone();
                     if *password == server_passwd {
                         self.user.register_state |= RegistrationFlags::PASS;
                     } else {
-                        let _ = self.connection.write(Message {
-                            tags: None,
-                            source: None,
-                            command: Command::ERR_PASSWDMISMATCH {
-                                client: self.user.nickname.clone(),
-                            },
+                        self.reply(Command::ERR_PASSWDMISMATCH {
+                            client: self.user.nickname.clone(),
                         }).await;
                     }
                 } else {
-                    let _ =self.connection.write(Message {
-                        tags: None,
-                        source: None,
-                        command: Command::ERR_ALREADYREGISTERED {
-                            client: self.user.nickname.clone(),
-                        },
+                    self.reply(Command::ERR_ALREADYREGISTERED {
+                        client: self.user.nickname.clone(),
                     }).await;
                 }
             },
+            WEBIRC { password, gateway, hostname, ip, options } => {
+                if self.user.register_state.intersects(RegistrationFlags::NICK | RegistrationFlags::USER) {
+                    self.reply(Command::ERR_ALREADYREGISTERED {
+                        client: self.user.nickname.clone(),
+                    }).await;
+                    return;
+                }
+
+                let peer = self.connection.address().ip().to_string();
+                let block = CONFIG.lock().unwrap().webirc.iter()
+                    .find(|block| block.name == gateway)
+                    .cloned();
+                let ip_address: Option<IpAddr> = ip.parse().ok();
+                match (block, ip_address) {
+                    (Some(block), Some(ip_address)) if block.password == password
+                        && block.hosts.iter().any(|host| mask::matches(host, &peer)) => {
+                        info!("{:} is {} via gateway {}", self.connection.address(), ip_address, gateway);
+                        // Reconnects are throttled per user, not per gateway.
+                        let throttled = self.throttle.lock().unwrap().connect(ip_address);
+                        if let Err(throttled) = throttled {
+                            if let Throttled::New = throttled {
+                                let config = CONFIG.lock().unwrap().throttle.clone();
+                                let _ = self.oper_tx.send(OperMsg::ServerNotice{
+                                    snomask: Snomask::FLOOD,
+                                    text: format!("Throttling {}: more than {} connections in {} seconds, banned for {} seconds",
+                                        ip_address, config.connections, config.period, config.ban_duration),
+                                }).await;
+                            }
+                            self.close_link("Throttled: Reconnecting too fast").await;
+                            return;
+                        }
+                        self.user.ip_address = ip_address;
+                        self.user.hostname = if is_valid_hostname(&hostname) {
+                            hostname
+                        } else {
+                            ip_address.to_string()
+                        };
+                        self.user.secure = options.as_deref()
+                            .is_some_and(|options| options.split(' ').any(|flag| flag == "secure"));
+                        self.user.gateway = Some(gateway);
+                        if let Some(lookup) = self.dnsbl.take() {
+                            lookup.abort();
+                        }
+                        if let Some(lookup) = self.hostname_lookup.take() {
+                            lookup.abort();
+                        }
+                        if let Some(lookup) = self.ident_lookup.take() {
+                            lookup.abort();
+                        }
+                        self.dnsbl = Some(tokio::spawn(dnsbl::check(self.resolver.clone(), ip_address)));
+
+                        match ClassSlot::assign(self.class.counts(), ip_address) {
+                            Ok(class) => {
+                                self.user.class = class.class.name.clone();
+                                self.class = class;
+                            },
+                            Err(reason) => self.close_link(&reason).await,
+                        }
+                    },
+                    (_, None) => self.close_link("WEBIRC: Invalid IP address").await,
+                    _ => self.close_link("WEBIRC: Invalid gateway credentials").await,
+                }
+            },
             NICK { nickname } => {
                 // TODO: check if contains disallowed characters (ERR_ERRONEUSNICKNAME)
-                // TODO: check if nick in useed on network (ERR_NICKNAMEINUSE)
-                if self.user.register_state.contains(RegistrationFlags::PASS) {
-                    self.user.nickname = nickname;
-                    self.user.register_state |= RegistrationFlags::NICK;
-                    if self.user.register_state.contains(RegistrationFlags::USER) {
-                        let (handler_tx, handler_rx) = mpsc::channel(1);
-                        self.handler_rx = handler_rx;
-                        let _ = self.oper_tx.send(OperMsg::AddUser{
-                            name: self.user.username.clone(),
-                            channel: handler_tx,
+                if !self.user.register_state.contains(RegistrationFlags::PASS) {
+                    return;
+                }
+                let nicklen = CONFIG.lock().unwrap().server.nicklen;
+                if nickname.len() > nicklen {
+                    self.reply(Command::ERR_ERRONEUSNICKNAME {
+                        client: if self.user.nickname.is_empty() { "*".to_string() } else { self.user.nickname.clone() },
+                        nick: nickname,
+                        text: "Erroneous Nickname".to_string(),
+                    }).await;
+                    return;
+                }
+                let qline = BANS.lock().unwrap().find_nickname(&nickname);
+                if let Some(ban) = qline {
+                    self.reply(Command::ERR_ERRONEUSNICKNAME {
+                        client: if self.user.nickname.is_empty() { "*".to_string() } else { self.user.nickname.clone() },
+                        nick: nickname,
+                        text: format!("Erroneous Nickname: {}", ban.reason),
+                    }).await;
+                    return;
+                }
+
+                if self.user.is_registered() {
+                    let (accepted_tx, accepted_rx) = oneshot::channel();
+                    let _ = self.oper_tx.send(OperMsg::ChangeNick{
+                        old_nickname: self.user.nickname.clone(),
+                        user: User { nickname: nickname.clone(), ..self.user.clone() },
+                        accepted: accepted_tx,
+                    }).await;
+                    if accepted_rx.await != Ok(true) {
+                        self.reply(Command::ERR_NICKNAMEINUSE {
+                            client: self.user.nickname.clone(),
+                            nick: nickname,
+                            text: "Nickname is already in use".to_string(),
                         }).await;
+                        return;
                     }
+                    self.user.nickname = nickname;
+                } else {
+                    self.user.nickname = nickname;
+                    self.user.register_state |= RegistrationFlags::NICK;
+                    self.register().await;
                 }
             },
             USER { user, mode, unused, realname } => {
-                if self.user.register_state.contains(RegistrationFlags::PASS) {
+                if self.user.register_state.contains(RegistrationFlags::PASS) && !self.user.is_registered() {
                     self.user.username = user;
                     self.user.realname = realname;
-                    self.user.register_state |= RegistrationFlags::NICK;
-                    if self.user.register_state.contains(RegistrationFlags::NICK) {
-                        let (handler_tx, handler_rx) = mpsc::channel(1);
-                        self.handler_rx = handler_rx;
-                        let _ = self.oper_tx.send(OperMsg::AddUser{
-                            name: self.user.username.clone(),
-                            channel: handler_tx,
-                        }).await;
-                    }
+                    self.user.register_state |= RegistrationFlags::USER;
+                    self.register().await;
                 }
             }
-            PRIVMSG { .. } => {
+            CAP { params } => {
+                let subcommand = params.first().map(|subcommand| subcommand.to_ascii_uppercase()).unwrap_or_default();
+                let argument = params.get(1).cloned().unwrap_or_default();
+                match subcommand.as_str() {
+                    "LS" => {
+                        if !self.user.is_registered() {
+                            self.user.register_state |= RegistrationFlags::CAP;
+                        }
+                        let names: Vec<&str> = CAPABILITIES.iter().map(|(name, _)| *name).collect();
+                        self.cap_reply("LS", names.join(" ")).await;
+                    },
+                    "LIST" => {
+                        let names: Vec<&str> = CAPABILITIES.iter()
+                            .filter(|(_, capability)| self.user.capabilities.contains(*capability))
+                            .map(|(name, _)| *name)
+                            .collect();
+                        self.cap_reply("LIST", names.join(" ")).await;
+                    },
+                    "REQ" => {
+                        if !self.user.is_registered() {
+                            self.user.register_state |= RegistrationFlags::CAP;
+                        }
+                        // Requests are all or nothing.
+                        let mut capabilities = self.user.capabilities;
+                        let mut valid = true;
+                        for name in argument.split(' ').filter(|name| !name.is_empty()) {
+                            let (enable, name) = match name.strip_prefix('-') {
+                                Some(name) => (false, name),
+                                None => (true, name),
+                            };
+                            match CAPABILITIES.iter().find(|(known, _)| *known == name) {
+                                Some((_, capability)) => capabilities.set(*capability, enable),
+                                None => valid = false,
+                            }
+                        }
+                        if !valid {
+                            self.cap_reply("NAK", argument).await;
+                            return;
+                        }
+                        self.user.capabilities = capabilities;
+                        if self.user.is_registered() {
+                            let _ = self.oper_tx.send(OperMsg::UpdateUser{
+                                user: self.user.clone(),
+                            }).await;
+                        }
+                        self.cap_reply("ACK", argument).await;
+                    },
+                    "END" => {
+                        if self.user.register_state.contains(RegistrationFlags::CAP) {
+                            self.user.register_state.remove(RegistrationFlags::CAP);
+                            self.register().await;
+                        }
+                    },
+                    _ => {
+                        self.reply(Command::ERR_INVALIDCAPCMD {
+                            client: if self.user.nickname.is_empty() { "*".to_string() } else { self.user.nickname.clone() },
+                            subcommand,
+                            text: "Invalid CAP command".to_string(),
+                        }).await;
+                    },
+                }
+            },
+            AWAY { text } => {
+                if !self.user.is_registered() {
+                    return;
+                }
+                self.user.away = text.filter(|text| !text.is_empty())
+                    .map(|text| text.chars().take(AWAYLEN).collect());
+                let _ = self.oper_tx.send(OperMsg::UpdateUser{
+                    user: self.user.clone(),
+                }).await;
+                if self.user.away.is_some() {
+                    self.reply(Command::RPL_NOWAWAY {
+                        client: self.user.nickname.clone(),
+                        text: "You have been marked as being away".to_string(),
+                    }).await;
+                } else {
+                    self.reply(Command::RPL_UNAWAY {
+                        client: self.user.nickname.clone(),
+                        text: "You are no longer marked as being away".to_string(),
+                    }).await;
+                }
+            },
+            WALLOPS { text } => {
+                let broadcast = OperMsg::Wallops{ nickname: self.user.nickname.clone(), text };
+                self.broadcast("WALLOPS", broadcast).await;
+            },
+            OPERWALL { text } => {
+                let broadcast = OperMsg::Operwall{ nickname: self.user.nickname.clone(), text };
+                self.broadcast("OPERWALL", broadcast).await;
+            },
+            GLOBOPS { text } => {
+                let broadcast = OperMsg::Operwall{ nickname: self.user.nickname.clone(), text };
+                self.broadcast("GLOBOPS", broadcast).await;
+            },
+            PRIVMSG { .. } | NOTICE { .. } if !self.user.is_registered() => {
+                self.reply(Command::ERR_NOTREGISTERED {
+                    client: if self.user.nickname.is_empty() { "*".to_string() } else { self.user.nickname.clone() },
+                    text: "You have not registered".to_string(),
+                }).await;
+            },
+            NOTICE { targets, text } if targets.starts_with('$') => {
+                let broadcast = OperMsg::Announce{ nickname: self.user.nickname.clone(), mask: targets, text };
+                self.broadcast("NOTICE", broadcast).await;
+            },
+            PRIVMSG { ref targets, .. } => {
+                let max_targets = CONFIG.lock().unwrap().server.max_targets;
+                if targets.split(',').count() > max_targets {
+                    self.reply(Command::ERR_TOOMANYTARGETS {
+                        client: self.user.nickname.clone(),
+                        target: targets.clone(),
+                        text: format!("Too many targets. The maximum is {}.", max_targets),
+                    }).await;
+                    return;
+                }
                 let _ = self.comm_tx.send(CommMsg{
                     user: self.user.clone(),
                     msg,
                 }).await;
             },
             JOIN { channels, keys } => {
-                if self.user.register_state.contains(RegistrationFlags::PASS & RegistrationFlags::NICK & RegistrationFlags::USER) {
+                if self.user.is_registered() {
+                    let channellen = CONFIG.lock().unwrap().server.channellen;
                     for channel in channels.split(',') {
+                        if !channel.starts_with(|c| CHANTYPES.contains(c)) || channel.len() > channellen {
+                            self.reply(Command::ERR_BADCHANMASK {
+                                client: self.user.nickname.clone(),
+                                channel: channel.to_string(),
+                                text: "Bad Channel Mask".to_string(),
+                            }).await;
+                            continue;
+                        }
+                        let qline = BANS.lock().unwrap().find_channel(channel);
+                        if let Some(ban) = qline {
+                            self.reply(Command::ERR_BADCHANMASK {
+                                client: self.user.nickname.clone(),
+                                channel: channel.to_string(),
+                                text: format!("Cannot join channel: {}", ban.reason),
+                            }).await;
+                            continue;
+                        }
                         let _ = self.oper_tx.send(OperMsg::JoinChannel{
                             nickname: self.user.nickname.clone(),
                             channel_name: channel.to_string(),
@@ -158,6 +1128,364 @@ impl Handler {
                     }
                 }
             },
+            MODE { target, modestring, args } => {
+                if !self.user.is_registered() {
+                    return;
+                }
+                if !target.eq_ignore_ascii_case(&self.user.nickname) {
+                    let _ = self.oper_tx.send(OperMsg::Mode{
+                        nickname: self.user.nickname.clone(),
+                        target,
+                    }).await;
+                    return;
+                }
+                match modestring {
+                    Some(modestring) => self.set_user_modes(&modestring, args).await,
+                    None => {
+                        self.reply(Command::RPL_UMODEIS {
+                            client: self.user.nickname.clone(),
+                            modes: self.user.mode_string(),
+                        }).await;
+                    },
+                }
+            },
+            VHOST { login, password } => {
+                if !self.user.is_registered() {
+                    return;
+                }
+                let block = CONFIG.lock().unwrap().vhost.iter()
+                    .find(|block| block.login == login)
+                    .cloned();
+                match block {
+                    Some(block) if block.password == password
+                        && block.hosts.iter().any(|host| self.user.matches_host(host)) => {
+                        info!("{} is now using vhost {}", self.user.nickname, block.vhost);
+                        self.user.vhost = Some(block.vhost);
+                        let _ = self.oper_tx.send(OperMsg::UpdateUser{
+                            user: self.user.clone(),
+                        }).await;
+                        self.host_hidden().await;
+                    },
+                    _ => self.notice(format!("*** Login for vhost {} failed", login)).await,
+                }
+            },
+            WHOIS { server: _, nickmasks } => {
+                if self.user.is_registered() {
+                    let max_targets = CONFIG.lock().unwrap().server.max_targets;
+                    if nickmasks.split(',').count() > max_targets {
+                        self.reply(Command::ERR_TOOMANYTARGETS {
+                            client: self.user.nickname.clone(),
+                            target: nickmasks,
+                            text: format!("Too many targets. The maximum is {}.", max_targets),
+                        }).await;
+                        return;
+                    }
+                    let _ = self.oper_tx.send(OperMsg::Whois{
+                        nickname: self.user.nickname.clone(),
+                        targets: nickmasks,
+                    }).await;
+                }
+            },
+            LIST { channels, server: _ } => {
+                if self.user.is_registered() {
+                    let _ = self.oper_tx.send(OperMsg::List{
+                        nickname: self.user.nickname.clone(),
+                        query: channels,
+                    }).await;
+                }
+            },
+            WHO { mask, options } => {
+                if self.user.is_registered() {
+                    let _ = self.oper_tx.send(OperMsg::Who{
+                        nickname: self.user.nickname.clone(),
+                        mask,
+                        options,
+                    }).await;
+                }
+            },
+            WHOWAS { nickname, count, server: _ } => {
+                if self.user.is_registered() {
+                    let _ = self.oper_tx.send(OperMsg::Whowas{
+                        nickname: self.user.nickname.clone(),
+                        target: nickname,
+                        count: count.and_then(|count| count.parse().ok()).filter(|count| *count > 0),
+                    }).await;
+                }
+            },
+            MOTD { server } => {
+                if !self.user.is_registered() {
+                    return;
+                }
+                if !self.is_other_server(server).await {
+                    self.motd().await;
+                }
+            },
+            LUSERS { mask: _, server: _ } => {
+                if self.user.is_registered() {
+                    let connections = self.class.counts().lock().unwrap().total();
+                    let _ = self.oper_tx.send(OperMsg::Lusers{
+                        nickname: self.user.nickname.clone(),
+                        connections,
+                    }).await;
+                }
+            },
+            VERSION { server } => {
+                if !self.user.is_registered() || self.is_other_server(server).await {
+                    return;
+                }
+                let server_name = CONFIG.lock().unwrap().server.name.clone();
+                self.reply(Command::RPL_VERSION {
+                    client: self.user.nickname.clone(),
+                    version: VERSION.to_string(),
+                    server: server_name,
+                    comments: String::new(),
+                }).await;
+                self.isupport().await;
+            },
+            TIME { server } => {
+                if !self.user.is_registered() || self.is_other_server(server).await {
+                    return;
+                }
+                let server_name = CONFIG.lock().unwrap().server.name.clone();
+                self.reply(Command::RPL_TIME {
+                    client: self.user.nickname.clone(),
+                    server: server_name,
+                    text: format_time(ban::now()),
+                }).await;
+            },
+            ADMIN { server } => {
+                if !self.user.is_registered() || self.is_other_server(server).await {
+                    return;
+                }
+                let (server_name, admin) = {
+                    let config = CONFIG.lock().unwrap();
+                    (config.server.name.clone(), config.admin.clone())
+                };
+                self.reply(Command::RPL_ADMINME {
+                    client: self.user.nickname.clone(),
+                    server: server_name,
+                    text: "Administrative info".to_string(),
+                }).await;
+                self.reply(Command::RPL_ADMINLOC1 {
+                    client: self.user.nickname.clone(),
+                    text: admin.location1,
+                }).await;
+                self.reply(Command::RPL_ADMINLOC2 {
+                    client: self.user.nickname.clone(),
+                    text: admin.location2,
+                }).await;
+                self.reply(Command::RPL_ADMINEMAIL {
+                    client: self.user.nickname.clone(),
+                    text: admin.email,
+                }).await;
+            },
+            INFO { server } => {
+                if !self.user.is_registered() || self.is_other_server(server).await {
+                    return;
+                }
+                let description = CONFIG.lock().unwrap().server.description.clone();
+                let info = [
+                    format!("{} -- {}", VERSION, description),
+                    format!("On-line since {}", format_time(*STARTED)),
+                ];
+                for text in info {
+                    self.reply(Command::RPL_INFO {
+                        client: self.user.nickname.clone(),
+                        text,
+                    }).await;
+                }
+                self.reply(Command::RPL_ENDOFINFO {
+                    client: self.user.nickname.clone(),
+                    text: "End of /INFO list.".to_string(),
+                }).await;
+            },
+            REHASH { option } => {
+                if !self.user.is_registered() {
+                    return;
+                }
+                if self.user.oper.is_none() {
+                    self.reply(Command::ERR_NOPRIVILEGES {
+                        client: self.user.nickname.clone(),
+                        text: "Permission Denied- You're not an IRC operator".to_string(),
+                    }).await;
+                    return;
+                }
+                // `REHASH MOTD` only re-reads the MOTD files.
+                let motd_only = option.is_some_and(|option| option.eq_ignore_ascii_case("MOTD"));
+                if !motd_only {
+                    let old_tokens = isupport::tokens();
+                    if let Err(err) = config::rehash() {
+                        self.notice(format!("*** Rehash failed: {}", err)).await;
+                        return;
+                    }
+                    let tokens = isupport::changes(&old_tokens, &isupport::tokens());
+                    if !tokens.is_empty() {
+                        let _ = self.oper_tx.send(OperMsg::Isupport{ tokens }).await;
+                    }
+                }
+                MOTD.lock().unwrap().reload();
+                info!("{} is rehashing the server", self.user.nickname);
+                self.reply(Command::RPL_REHASHING {
+                    client: self.user.nickname.clone(),
+                    config_file: CONFIG_PATH.to_string(),
+                    text: "Rehashing".to_string(),
+                }).await;
+            },
+            OPER { name, password } => {
+                if !self.user.is_registered() {
+                    return;
+                }
+                let block = CONFIG.lock().unwrap().oper.iter()
+                    .find(|block| block.name == name)
+                    .cloned();
+                match block {
+                    Some(block) if !block.hosts.iter().any(|host| self.user.matches_host(host)) => {
+                        self.reply(Command::ERR_NOOPERHOST {
+                            client: self.user.nickname.clone(),
+                            text: "No O-lines for your host".to_string(),
+                        }).await;
+                    },
+                    Some(block) if block.password == password => {
+                        info!("{} is now an operator ({})", self.user.nickname, name);
+                        self.user.oper = Some(name);
+                        self.user.modes.insert(UserModes::OPER);
+                        self.user.snomask = Snomask::empty().apply(&block.snomask);
+                        if !self.user.snomask.is_empty() {
+                            self.user.modes.insert(UserModes::SNOTICE);
+                        }
+                        let _ = self.oper_tx.send(OperMsg::UpdateUser{
+                            user: self.user.clone(),
+                        }).await;
+                        self.reply(Command::RPL_YOUREOPER {
+                            client: self.user.nickname.clone(),
+                            text: "You are now an IRC operator".to_string(),
+                        }).await;
+                    },
+                    _ => {
+                        self.reply(Command::ERR_PASSWDMISMATCH {
+                            client: self.user.nickname.clone(),
+                        }).await;
+                    },
+                }
+            },
+            STATS { query, server: _ } => {
+                if !self.user.is_registered() {
+                    return;
+                }
+                if self.user.oper.is_none() {
+                    self.reply(Command::ERR_NOPRIVILEGES {
+                        client: self.user.nickname.clone(),
+                        text: "Permission Denied- You're not an IRC operator".to_string(),
+                    }).await;
+                    return;
+                }
+
+                let letter = query.unwrap_or_default();
+                match letter.as_str() {
+                    "y" | "Y" => {
+                        let (mut classes, default_ping_frequency) = {
+                            let config = CONFIG.lock().unwrap();
+                            (config.class.clone(), config.server.ping_frequency)
+                        };
+                        if classes.is_empty() {
+                            classes.push(self.class.class.clone());
+                        }
+                        for class in classes {
+                            let clients = self.class.counts().lock().unwrap().clients(&class.name);
+                            self.reply(Command::RPL_STATSYLINE {
+                                client: self.user.nickname.clone(),
+                                class: class.name.clone(),
+                                ping_frequency: class.ping_frequency.unwrap_or(default_ping_frequency).to_string(),
+                                connect_frequency: "0".to_string(),
+                                max_sendq: class.sendq.to_string(),
+                                clients: format!("{}/{}", clients, class.max_clients.map_or("*".to_string(), |max| max.to_string())),
+                            }).await;
+                        }
+                    },
+                    "t" | "T" => {
+                        let text = {
+                            let throttle = self.throttle.lock().unwrap();
+                            format!("Throttle: {} connections refused, {} bans placed, {} bans active",
+                                throttle.refused, throttle.banned, throttle.active_bans())
+                        };
+                        self.reply(Command::RPL_STATSDEBUG {
+                            client: self.user.nickname.clone(),
+                            text,
+                        }).await;
+                    },
+                    "k" | "K" | "g" | "G" | "d" | "D" | "x" | "X" | "q" | "Q" | "s" | "S" => {
+                        let kind = match letter.as_str() {
+                            "k" | "K" => BanKind::Kline,
+                            "g" | "G" => BanKind::Gline,
+                            "d" | "D" => BanKind::Dline,
+                            "x" | "X" => BanKind::Xline,
+                            "q" | "Q" => BanKind::Qline,
+                            _ => BanKind::Shun,
+                        };
+                        let bans = BANS.lock().unwrap().list(kind);
+                        for ban in bans {
+                            let client = self.user.nickname.clone();
+                            let set_at = ban.set_at.to_string();
+                            let expires_at = ban.expires_at.unwrap_or(0).to_string();
+                            let Ban { mask, reason, setter, .. } = ban;
+                            self.reply(match kind {
+                                BanKind::Kline => Command::RPL_STATSKLINE { client, mask, set_at, expires_at, setter, reason },
+                                BanKind::Gline => Command::RPL_STATSGLINE { client, mask, set_at, expires_at, setter, reason },
+                                BanKind::Dline => Command::RPL_STATSDLINE { client, mask, set_at, expires_at, setter, reason },
+                                BanKind::Xline => Command::RPL_STATSXLINE { client, mask, set_at, expires_at, setter, reason },
+                                BanKind::Qline => Command::RPL_STATSQLINE { client, mask, set_at, expires_at, setter, reason },
+                                BanKind::Shun => Command::RPL_STATSSHUN { client, mask, set_at, expires_at, setter, reason },
+                            }).await;
+                        }
+                    },
+                    "f" | "F" => {
+                        let filters = SPAMFILTERS.lock().unwrap().list();
+                        for filter in filters {
+                            self.reply(Command::RPL_STATSSPAMF {
+                                client: self.user.nickname.clone(),
+                                targets: filter.target_letters(),
+                                action: filter.action.name().to_string(),
+                                duration: filter.duration.unwrap_or(0).to_string(),
+                                hits: filter.hits.to_string(),
+                                setter: filter.setter,
+                                mask: filter.mask,
+                                reason: filter.reason,
+                            }).await;
+                        }
+                    },
+                    _ => {},
+                }
+                self.reply(Command::RPL_ENDOFSTATS {
+                    client: self.user.nickname.clone(),
+                    stats_letter: letter,
+                    text: "End of /STATS report".to_string(),
+                }).await;
+            },
+            KLINE { duration, mask, reason } => self.add_ban(BanKind::Kline, duration, mask, reason).await,
+            UNKLINE { mask } => self.remove_ban(BanKind::Kline, mask).await,
+            GLINE { duration, mask, reason } => self.add_ban(BanKind::Gline, duration, mask, reason).await,
+            UNGLINE { mask } => self.remove_ban(BanKind::Gline, mask).await,
+            DLINE { duration, mask, reason } => self.add_ban(BanKind::Dline, duration, mask, reason).await,
+            UNDLINE { mask } => self.remove_ban(BanKind::Dline, mask).await,
+            XLINE { duration, mask, reason } => self.add_ban(BanKind::Xline, duration, mask, reason).await,
+            UNXLINE { mask } => self.remove_ban(BanKind::Xline, mask).await,
+            QLINE { duration, mask, reason } | RESV { duration, mask, reason } => {
+                self.add_ban(BanKind::Qline, duration, mask, reason).await;
+            },
+            UNQLINE { mask } | UNRESV { mask } => self.remove_ban(BanKind::Qline, mask).await,
+            SHUN { duration, mask, reason } => self.add_ban(BanKind::Shun, duration, mask, reason).await,
+            UNSHUN { mask } => self.remove_ban(BanKind::Shun, mask).await,
+            SPAMFILTER { targets, action, duration, mask, reason } => {
+                self.add_spamfilter(targets, action, duration, mask, reason).await;
+            },
+            UNSPAMFILTER { mask } => self.remove_spamfilter(mask).await,
+            QUIT { reason } => {
+                let reason = match reason {
+                    Some(reason) => format!("Quit: {}", reason),
+                    None => "Quit".to_string(),
+                };
+                self.close_link(&reason).await;
+            },
 
             _ => {},
         }
@@ -165,3 +1493,9 @@ impl Handler {
     }
 
 }
+
+fn is_valid_hostname(hostname: &str) -> bool {
+    return !hostname.is_empty() && hostname.len() <= 63
+        && !hostname.starts_with(['.', '-', ':'])
+        && hostname.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':'));
+}
@@ -0,0 +1,44 @@
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpSocket;
+use tokio::time::{timeout, Duration};
+
+use crate::config::CONFIG;
+
+/// Asks the identd on the client's host who owns the connection between
+/// `local` and `peer` (RFC 1413), giving up after `ident.timeout` seconds.
+pub async fn lookup(local: SocketAddr, peer: SocketAddr) -> Option<String> {
+    let config = CONFIG.lock().unwrap().ident.clone();
+    let identd = SocketAddr::new(peer.ip(), config.port);
+    return timeout(Duration::from_secs(config.timeout), query(local, peer, identd)).await.ok().flatten();
+}
+
+async fn query(local: SocketAddr, peer: SocketAddr, identd: SocketAddr) -> Option<String> {
+    let socket = match identd {
+        SocketAddr::V4(_) => TcpSocket::new_v4(),
+        SocketAddr::V6(_) => TcpSocket::new_v6(),
+    }.ok()?;
+    socket.bind(SocketAddr::new(local.ip(), 0)).ok()?;
+    let mut stream = socket.connect(identd).await.ok()?;
+    stream.write_all(format!("{}, {}\r\n", peer.port(), local.port()).as_bytes()).await.ok()?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).take(512).read_line(&mut reply).await.ok()?;
+    return parse_reply(&reply);
+}
+
+/// Takes the user id out of a `<ports> : USERID : <system> : <user id>` reply.
+fn parse_reply(reply: &str) -> Option<String> {
+    let mut fields = reply.trim_end().splitn(4, ':').map(str::trim);
+    fields.next()?;
+    if fields.next()? != "USERID" {
+        return None;
+    }
+    fields.next()?;
+    let userid: String = fields.next()?.chars().take(10).collect();
+    if userid.is_empty() || !userid.chars().all(|c| c.is_ascii_graphic() && !matches!(c, '@' | '!' | ':')) {
+        return None;
+    }
+    return Some(userid);
+}
@@ -0,0 +1,57 @@
+use crate::config::CONFIG;
+use crate::user::AWAYLEN;
+
+/// The prefixes a channel name can start with.
+pub const CHANTYPES: &str = "#";
+
+/// How many tokens go in one RPL_ISUPPORT line.
+const TOKENS_PER_LINE: usize = 13;
+
+/// The RPL_ISUPPORT tokens this server advertises, from the same limits the
+/// handler and bridge enforce. There are no channel modes, membership
+/// prefixes or topics yet, so CHANMODES and PREFIX are empty and MODES,
+/// MAXLIST and TOPICLEN are left out.
+pub fn tokens() -> Vec<String> {
+    let server = CONFIG.lock().unwrap().server.clone();
+    let mut tokens = vec![
+        format!("AWAYLEN={}", AWAYLEN),
+        "CASEMAPPING=ascii".to_string(),
+        format!("CHANNELLEN={}", server.channellen),
+        "CHANMODES=,,,".to_string(),
+        format!("CHANTYPES={}", CHANTYPES),
+        // No T until channels keep topics.
+        "ELIST=CMNU".to_string(),
+    ];
+    if let Some(network) = server.network {
+        tokens.push(format!("NETWORK={}", network));
+    }
+    tokens.extend([
+        format!("NICKLEN={}", server.nicklen),
+        "PREFIX=".to_string(),
+        "SAFELIST".to_string(),
+        format!("TARGMAX=JOIN:,PRIVMSG:{},WHOIS:{}", server.max_targets, server.max_targets),
+        "WHOX".to_string(),
+    ]);
+    return tokens;
+}
+
+/// `tokens` split into the parameters of each RPL_ISUPPORT line.
+pub fn lines(tokens: &[String]) -> Vec<Vec<String>> {
+    return tokens.chunks(TOKENS_PER_LINE).map(<[String]>::to_vec).collect();
+}
+
+/// The tokens to send clients that were given `old` so they end up with
+/// `new`: new and changed tokens, and `-NAME` for removed ones.
+pub fn changes(old: &[String], new: &[String]) -> Vec<String> {
+    let name = |token: &str| token.split('=').next().unwrap_or_default().to_string();
+    let mut changes: Vec<String> = new.iter()
+        .filter(|token| !old.contains(token))
+        .cloned()
+        .collect();
+    for token in old {
+        if !new.iter().any(|new_token| name(new_token) == name(token)) {
+            changes.push(format!("-{}", name(token)));
+        }
+    }
+    return changes;
+}
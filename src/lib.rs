@@ -3,3 +3,21 @@ pub mod config;
 pub mod handler;
 pub mod user;
 pub mod bridge;
+pub mod ban;
+pub mod mask;
+pub mod transport;
+pub mod websocket;
+pub mod flood;
+pub mod class;
+pub mod throttle;
+pub mod spamfilter;
+pub mod resolver;
+pub mod dnsbl;
+pub mod ident;
+pub mod cloak;
+pub mod isupport;
+pub mod list;
+pub mod motd;
+pub mod snomask;
+pub mod date;
+pub mod sendq;
@@ -0,0 +1,79 @@
+use crate::mask;
+
+/// One ELIST condition from a LIST query.
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    /// `>n`: more than n members.
+    MoreUsers(usize),
+    /// `<n`: fewer than n members.
+    FewerUsers(usize),
+    /// `C<n`: created less than n minutes ago.
+    CreatedWithin(u64),
+    /// `C>n`: created more than n minutes ago.
+    CreatedBefore(u64),
+    /// `T<n`: topic changed less than n minutes ago.
+    TopicWithin(u64),
+    /// `T>n`: topic changed more than n minutes ago.
+    TopicBefore(u64),
+    /// `!mask`: name does not match.
+    Exclude(String),
+}
+
+/// A parsed LIST query: channel masks, any of which may match, and ELIST
+/// conditions, all of which must hold.
+#[derive(Debug, Clone, Default)]
+pub struct ListQuery {
+    masks: Vec<String>,
+    conditions: Vec<Condition>,
+}
+
+/// What a LIST query is matched against.
+pub struct ListEntry<'a> {
+    pub name: &'a str,
+    pub users: usize,
+    /// When the channel was created, in seconds since the Unix epoch.
+    pub created: u64,
+    /// When the topic was last set, if it ever was.
+    pub topic_time: Option<u64>,
+}
+
+impl ListQuery {
+    /// Parses the comma separated first parameter of LIST. Conditions with
+    /// an unreadable number are ignored.
+    pub fn parse(query: &str) -> Self {
+        let mut list_query = ListQuery::default();
+        for item in query.split(',').filter(|item| !item.is_empty()) {
+            let condition = match item.as_bytes() {
+                [b'>', ..] => item[1..].parse().ok().map(Condition::MoreUsers),
+                [b'<', ..] => item[1..].parse().ok().map(Condition::FewerUsers),
+                [b'C' | b'c', b'<', ..] => item[2..].parse().ok().map(Condition::CreatedWithin),
+                [b'C' | b'c', b'>', ..] => item[2..].parse().ok().map(Condition::CreatedBefore),
+                [b'T' | b't', b'<', ..] => item[2..].parse().ok().map(Condition::TopicWithin),
+                [b'T' | b't', b'>', ..] => item[2..].parse().ok().map(Condition::TopicBefore),
+                [b'!', ..] => Some(Condition::Exclude(item[1..].to_string())),
+                _ => {
+                    list_query.masks.push(item.to_string());
+                    continue;
+                },
+            };
+            list_query.conditions.extend(condition);
+        }
+        return list_query;
+    }
+
+    pub fn matches(&self, entry: &ListEntry, now: u64) -> bool {
+        if !self.masks.is_empty() && !self.masks.iter().any(|mask| mask::matches(mask, entry.name)) {
+            return false;
+        }
+        let age = |time: u64| now.saturating_sub(time) / 60;
+        return self.conditions.iter().all(|condition| match condition {
+            Condition::MoreUsers(count) => entry.users > *count,
+            Condition::FewerUsers(count) => entry.users < *count,
+            Condition::CreatedWithin(minutes) => age(entry.created) < *minutes,
+            Condition::CreatedBefore(minutes) => age(entry.created) > *minutes,
+            Condition::TopicWithin(minutes) => entry.topic_time.is_some_and(|time| age(time) < *minutes),
+            Condition::TopicBefore(minutes) => entry.topic_time.is_some_and(|time| age(time) > *minutes),
+            Condition::Exclude(mask) => !mask::matches(mask, entry.name),
+        });
+    }
+}
@@ -0,0 +1,73 @@
+use std::net::IpAddr;
+
+use regex::{Regex, RegexBuilder};
+
+/// Matches `text` against an IRC style wildcard `mask`, where `*` matches any
+/// run of characters and `?` matches exactly one. Comparison ignores ASCII case.
+pub fn matches(mask: &str, text: &str) -> bool {
+    let mask: Vec<char> = mask.to_ascii_lowercase().chars().collect();
+    let text: Vec<char> = text.to_ascii_lowercase().chars().collect();
+
+    let (mut m, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if m < mask.len() && (mask[m] == '?' || mask[m] == text[t]) {
+            m += 1;
+            t += 1;
+        } else if m < mask.len() && mask[m] == '*' {
+            star = Some((m, t));
+            m += 1;
+        } else if let Some((star_m, star_t)) = star {
+            m = star_m + 1;
+            t = star_t + 1;
+            star = Some((star_m, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    while m < mask.len() && mask[m] == '*' {
+        m += 1;
+    }
+    return m == mask.len();
+}
+
+/// Matches `address` against either a CIDR block such as `192.0.2.0/24` or
+/// a wildcard mask over its textual form.
+pub fn matches_address(mask: &str, address: IpAddr) -> bool {
+    let Some((network, prefix)) = mask.split_once('/') else {
+        return matches(mask, &address.to_string());
+    };
+    let (Ok(network), Ok(prefix)) = (network.parse::<IpAddr>(), prefix.parse::<u32>()) else {
+        return false;
+    };
+    match (network, address) {
+        (IpAddr::V4(network), IpAddr::V4(address)) if prefix <= 32 => {
+            let netmask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            return u32::from(network) & netmask == u32::from(address) & netmask;
+        },
+        (IpAddr::V6(network), IpAddr::V6(address)) if prefix <= 128 => {
+            let netmask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            return u128::from(network) & netmask == u128::from(address) & netmask;
+        },
+        _ => return false,
+    }
+}
+
+/// The case insensitive regular expression of a mask written as
+/// `/pattern/`, `None` for other masks and invalid expressions.
+pub fn compile_pattern(mask: &str) -> Option<Regex> {
+    let pattern = mask.strip_prefix('/')?.strip_suffix('/')?;
+    return RegexBuilder::new(pattern).case_insensitive(true).build().ok();
+}
+
+pub fn is_regex(mask: &str) -> bool {
+    return mask.len() >= 2 && mask.starts_with('/') && mask.ends_with('/');
+}
+
+/// Whether `mask` is a valid wildcard or `/pattern/` mask.
+pub fn is_valid_pattern(mask: &str) -> bool {
+    match mask.strip_prefix('/').and_then(|mask| mask.strip_suffix('/')) {
+        Some(pattern) => RegexBuilder::new(pattern).build().is_ok(),
+        None => !mask.is_empty(),
+    }
+}
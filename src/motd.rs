@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::sync::{Arc, Mutex};
+
+use log::{info, warn};
+use once_cell::sync::Lazy;
+
+use crate::config::CONFIG;
+
+pub static MOTD: Lazy<Arc<Mutex<Motd>>> = Lazy::new(|| {
+    Arc::new(Mutex::new(Motd::load()))
+});
+
+/// The server's message of the day and the alternate ones of connection
+/// classes, read once and kept until the next REHASH.
+pub struct Motd {
+    default: Option<Vec<String>>,
+    classes: HashMap<String, Vec<String>>,
+}
+
+impl Motd {
+    pub fn load() -> Self {
+        let config = CONFIG.lock().unwrap().clone();
+        let default = config.server.motd_file.as_deref().and_then(read_lines);
+        let mut classes = HashMap::new();
+        for class in config.class {
+            if let Some(lines) = class.motd_file.as_deref().and_then(read_lines) {
+                classes.insert(class.name, lines);
+            }
+        }
+        info!("Loaded {} alternate MOTDs", classes.len());
+        return Motd { default, classes };
+    }
+
+    pub fn reload(&mut self) {
+        *self = Motd::load();
+    }
+
+    /// The lines sent to a client of `class`, `None` when there is no MOTD.
+    pub fn lines(&self, class: &str) -> Option<Vec<String>> {
+        return self.classes.get(class).or(self.default.as_ref()).cloned();
+    }
+}
+
+fn read_lines(path: &str) -> Option<Vec<String>> {
+    match read_to_string(path) {
+        Ok(contents) => return Some(contents.lines().map(str::to_string).collect()),
+        Err(err) => {
+            warn!("Could not read MOTD from file \"{}\": {}", path, err);
+            return None;
+        },
+    }
+}
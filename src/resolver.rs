@@ -0,0 +1,121 @@
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+use log::warn;
+use tokio::time::{timeout, Duration};
+
+use crate::config::CONFIG;
+
+pub type Lookup<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// DNS lookups made for connecting clients. The server uses `SystemResolver`,
+/// tests can hand `start_server_with_resolver` a resolver of their own.
+pub trait Resolver: Send + Sync {
+    /// The IPv4 addresses `name` resolves to, empty when it doesn't exist.
+    fn lookup_ipv4(&self, name: String) -> Lookup<'_, Vec<Ipv4Addr>>;
+    /// The IPv4 and IPv6 addresses `name` resolves to.
+    fn lookup_ip(&self, name: String) -> Lookup<'_, Vec<IpAddr>>;
+    /// The names `ip_address` points back to, without the trailing dot.
+    fn lookup_ptr(&self, ip_address: IpAddr) -> Lookup<'_, Vec<String>>;
+}
+
+/// Resolves nothing. `start_server` uses it so tests never touch the network.
+pub struct NoResolver;
+
+impl Resolver for NoResolver {
+    fn lookup_ipv4(&self, _name: String) -> Lookup<'_, Vec<Ipv4Addr>> {
+        return Box::pin(async { Vec::new() });
+    }
+
+    fn lookup_ip(&self, _name: String) -> Lookup<'_, Vec<IpAddr>> {
+        return Box::pin(async { Vec::new() });
+    }
+
+    fn lookup_ptr(&self, _ip_address: IpAddr) -> Lookup<'_, Vec<String>> {
+        return Box::pin(async { Vec::new() });
+    }
+}
+
+/// Resolves through the nameservers in the system configuration.
+pub struct SystemResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl SystemResolver {
+    pub fn new() -> Self {
+        let resolver = match TokioAsyncResolver::tokio_from_system_conf() {
+            Ok(resolver) => resolver,
+            Err(err) => {
+                warn!("Could not read the system resolver configuration: {}", err);
+                TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+            },
+        };
+        return SystemResolver { resolver };
+    }
+}
+
+impl Default for SystemResolver {
+    fn default() -> Self {
+        return SystemResolver::new();
+    }
+}
+
+impl Resolver for SystemResolver {
+    fn lookup_ipv4(&self, name: String) -> Lookup<'_, Vec<Ipv4Addr>> {
+        return Box::pin(async move {
+            match self.resolver.ipv4_lookup(name).await {
+                Ok(lookup) => lookup.iter().map(|record| record.0).collect(),
+                Err(_) => Vec::new(),
+            }
+        });
+    }
+
+    fn lookup_ip(&self, name: String) -> Lookup<'_, Vec<IpAddr>> {
+        return Box::pin(async move {
+            match self.resolver.lookup_ip(name).await {
+                Ok(lookup) => lookup.iter().collect(),
+                Err(_) => Vec::new(),
+            }
+        });
+    }
+
+    fn lookup_ptr(&self, ip_address: IpAddr) -> Lookup<'_, Vec<String>> {
+        return Box::pin(async move {
+            match self.resolver.reverse_lookup(ip_address).await {
+                Ok(lookup) => lookup.iter()
+                    .map(|record| record.0.to_utf8().trim_end_matches('.').to_string())
+                    .collect(),
+                Err(_) => Vec::new(),
+            }
+        });
+    }
+}
+
+pub enum Hostname {
+    /// The address points to this name and the name points back to it.
+    Found(String),
+    /// The address has no name, or the lookup timed out.
+    NotFound,
+    /// The name the address points to doesn't resolve back to it.
+    Mismatch,
+}
+
+/// Reverse resolves `ip_address` and confirms the result with a forward
+/// lookup, giving up after `dns_timeout` seconds.
+pub async fn lookup_hostname(resolver: Arc<dyn Resolver>, ip_address: IpAddr) -> Hostname {
+    let dns_timeout = CONFIG.lock().unwrap().server.dns_timeout;
+    let lookup = async {
+        let Some(hostname) = resolver.lookup_ptr(ip_address).await.into_iter().next() else {
+            return Hostname::NotFound;
+        };
+        if !resolver.lookup_ip(hostname.clone()).await.contains(&ip_address) {
+            return Hostname::Mismatch;
+        }
+        return Hostname::Found(hostname);
+    };
+    return timeout(Duration::from_secs(dns_timeout), lookup).await.unwrap_or(Hostname::NotFound);
+}
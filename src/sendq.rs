@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use irc_proto::types::Message;
+use tokio::sync::{mpsc, Notify};
+
+#[derive(Debug)]
+struct State {
+    /// Bytes queued and not yet taken by the handler.
+    queued: AtomicUsize,
+    exceeded: AtomicBool,
+    notify: Notify,
+}
+
+/// Creates a send queue holding at most `limit` bytes of messages.
+pub fn channel(limit: usize) -> (SendQ, SendQReceiver) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let state = Arc::new(State {
+        queued: AtomicUsize::new(0),
+        exceeded: AtomicBool::new(false),
+        notify: Notify::new(),
+    });
+    return (SendQ { tx, state: state.clone(), limit }, SendQReceiver { rx, state });
+}
+
+/// The bridge's end of a client's send queue. Pushing never waits, so a
+/// client that stops reading can't hold up the bridge.
+#[derive(Debug, Clone)]
+pub struct SendQ {
+    tx: mpsc::UnboundedSender<Message>,
+    state: Arc<State>,
+    limit: usize,
+}
+
+impl SendQ {
+    /// Queues `message`. Fails once the queue would hold more than its
+    /// limit, and keeps failing after that; the handler is told to drop the
+    /// client.
+    pub fn push(&self, message: Message) -> Result<(), ()> {
+        if self.state.exceeded.load(Ordering::Relaxed) {
+            return Err(());
+        }
+        let bytes = message.to_bytes().len();
+        if self.state.queued.load(Ordering::Relaxed) + bytes > self.limit {
+            self.state.exceeded.store(true, Ordering::Relaxed);
+            self.state.notify.notify_one();
+            return Err(());
+        }
+        self.state.queued.fetch_add(bytes, Ordering::Relaxed);
+        // A closed queue means the handler is already on its way out.
+        let _ = self.tx.send(message);
+        return Ok(());
+    }
+}
+
+/// The handler's end of a client's send queue.
+pub struct SendQReceiver {
+    rx: mpsc::UnboundedReceiver<Message>,
+    state: Arc<State>,
+}
+
+impl SendQReceiver {
+    pub async fn recv(&mut self) -> Option<Message> {
+        let message = self.rx.recv().await?;
+        self.state.queued.fetch_sub(message.to_bytes().len(), Ordering::Relaxed);
+        return Some(message);
+    }
+
+    /// A handle that resolves once the queue has overflowed, for waiting on
+    /// while a message is being received or written.
+    pub fn overflow(&self) -> Overflow {
+        return Overflow { state: self.state.clone() };
+    }
+}
+
+pub struct Overflow {
+    state: Arc<State>,
+}
+
+impl Overflow {
+    pub async fn exceeded(&self) {
+        if self.state.exceeded.load(Ordering::Relaxed) {
+            return;
+        }
+        self.state.notify.notified().await;
+    }
+}
@@ -1,6 +1,8 @@
 use std::future::Future;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use log::{info, warn};
+use once_cell::sync::Lazy;
 
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{broadcast, mpsc};
@@ -8,13 +10,30 @@ use tokio::time::{self, Duration};
 
 use irc_proto::connection::Connection;
 
+use crate::ban::{self, BANS};
 use crate::bridge::{Bridge, CommMsg, OperMsg};
+use crate::class::{ClassCounts, ClassSlot};
+use crate::config::CONFIG;
+use crate::throttle::{Throttled, Throttler};
 use crate::handler::Handler;
+use crate::resolver::{NoResolver, Resolver};
+use crate::snomask::Snomask;
+use crate::transport::Transport;
+use crate::websocket::WebSocketAcceptor;
 
 const BACKOFF_LIMIT: u64 = 64;
 
+pub const VERSION: &str = concat!(env!("CARGO_PKG_NAME"), "-", env!("CARGO_PKG_VERSION"));
+
+/// When the server started, in seconds since the Unix epoch.
+pub static STARTED: Lazy<u64> = Lazy::new(ban::now);
+
 struct Listener {
     listener: TcpListener,
+    websocket: Option<Arc<WebSocketAcceptor>>,
+    classes: Arc<Mutex<ClassCounts>>,
+    throttle: Arc<Mutex<Throttler>>,
+    resolver: Arc<dyn Resolver>,
     notify_shutdown: broadcast::Sender<()>,
 }
 
@@ -22,16 +41,89 @@ impl Listener {
     async fn run(&self, oper_tx: mpsc::Sender<OperMsg>, comm_tx: mpsc::Sender<CommMsg>) -> Result<(), ()> {
         loop {
             let (stream, address) = self.accept().await?;
-
-            let mut handler = Handler::new(
-                Connection::new(stream, address),
-                oper_tx.clone(),
-                comm_tx.clone(),
-                self.notify_shutdown.subscribe(),
-            );
-            info!("{:} connected", handler.connection.address());
+            let local_address = match stream.local_addr() {
+                Ok(local_address) => local_address,
+                Err(_) => continue,
+            };
+
+            let oper_tx = oper_tx.clone();
+            let comm_tx = comm_tx.clone();
+            let shutdown = self.notify_shutdown.subscribe();
+            let websocket = self.websocket.clone();
+            let classes = self.classes.clone();
+            let throttle = self.throttle.clone();
+            let resolver = self.resolver.clone();
 
             tokio::spawn(async move {
+                // Banned and throttled addresses are refused before any TLS
+                // or WebSocket handshake is spent on them.
+                let dline = BANS.lock().unwrap().find_address(address.ip());
+                let refusal = match dline {
+                    Some(ban) => {
+                        info!("{:} rejected: {}", address, ban.disconnect_reason());
+                        let _ = oper_tx.send(OperMsg::ServerNotice{
+                            snomask: Snomask::REJECTS,
+                            text: format!("Rejected connection from {}: {}", address.ip(), ban.disconnect_reason()),
+                        }).await;
+                        Some(ban.disconnect_reason())
+                    },
+                    None => {
+                        let throttled = throttle.lock().unwrap().connect(address.ip());
+                        match throttled {
+                            Err(throttled) => {
+                                if let Throttled::New = throttled {
+                                    let config = CONFIG.lock().unwrap().throttle.clone();
+                                    let _ = oper_tx.send(OperMsg::ServerNotice{
+                                        snomask: Snomask::FLOOD,
+                                        text: format!("Throttling {}: more than {} connections in {} seconds, banned for {} seconds",
+                                            address.ip(), config.connections, config.period, config.ban_duration),
+                                    }).await;
+                                }
+                                info!("{:} throttled", address);
+                                Some("Throttled: Reconnecting too fast".to_string())
+                            },
+                            Ok(_) => None,
+                        }
+                    },
+                };
+                if let Some(reason) = refusal {
+                    // WebSocket clients are dropped without a reason, which
+                    // would need a finished handshake to be sent.
+                    if websocket.is_none() {
+                        Transport::Tcp(Connection::new(stream, address)).close_link(&reason).await;
+                    }
+                    return;
+                }
+
+                let handshake_timeout = Duration::from_secs(CONFIG.lock().unwrap().server.registration_timeout);
+                let mut connection = match websocket {
+                    Some(acceptor) => match time::timeout(handshake_timeout, acceptor.accept(stream, address)).await {
+                        Ok(Ok(connection)) => Transport::WebSocket(Box::new(connection)),
+                        Ok(Err(())) => return,
+                        Err(_) => {
+                            info!("{:} handshake timed out", address);
+                            return;
+                        },
+                    },
+                    None => Transport::Tcp(Connection::new(stream, address)),
+                };
+
+                let class = match ClassSlot::assign(&classes, address.ip()) {
+                    Ok(class) => class,
+                    Err(reason) => {
+                        info!("{:} rejected: {}", address, reason);
+                        let _ = oper_tx.send(OperMsg::ServerNotice{
+                            snomask: Snomask::REJECTS,
+                            text: format!("Rejected connection from {}: {}", address.ip(), reason),
+                        }).await;
+                        connection.close_link(&reason).await;
+                        return;
+                    },
+                };
+
+                let mut handler = Handler::new(connection, local_address, class, throttle, resolver, oper_tx, comm_tx, shutdown);
+                info!("{:} connected", handler.connection.address());
+
                 if (handler.run().await).is_err() {
                     info!("{:} exited", handler.connection.address());
                 }
@@ -58,13 +150,42 @@ impl Listener {
 }
 
 
-pub async fn run(listener: TcpListener, shutdown: impl Future) -> Result<(), ()> {
+pub async fn run(
+    listener: TcpListener,
+    websockets: Vec<(TcpListener, WebSocketAcceptor)>,
+    resolver: Arc<dyn Resolver>,
+    shutdown: impl Future,
+) -> Result<(), ()> {
+    Lazy::force(&STARTED);
     let (notify_shutdown, _) = broadcast::channel(1);
     let (oper_tx, oper_rx) = mpsc::channel(1);
     let (comm_tx, comm_rx) = mpsc::channel(1);
+    let classes = Arc::new(Mutex::new(ClassCounts::default()));
+    let throttle = Arc::new(Mutex::new(Throttler::default()));
+
+    let mut websocket_tasks = Vec::new();
+    for (listener, acceptor) in websockets {
+        let websocket = Listener {
+            listener,
+            websocket: Some(Arc::new(acceptor)),
+            classes: classes.clone(),
+            throttle: throttle.clone(),
+            resolver: resolver.clone(),
+            notify_shutdown: notify_shutdown.clone(),
+        };
+        let oper_tx = oper_tx.clone();
+        let comm_tx = comm_tx.clone();
+        websocket_tasks.push(tokio::spawn(async move {
+            websocket.run(oper_tx, comm_tx).await
+        }));
+    }
 
     let server = Listener {
         listener,
+        websocket: None,
+        classes,
+        throttle,
+        resolver,
         notify_shutdown,
     };
     let mut bridge = Bridge::new(oper_rx, comm_rx);
@@ -79,6 +200,9 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) -> Result<(), ()>
         _ = shutdown => {}
     }
 
+    for task in websocket_tasks {
+        task.abort();
+    }
     let Listener{
         notify_shutdown,
         ..
@@ -89,15 +213,36 @@ pub async fn run(listener: TcpListener, shutdown: impl Future) -> Result<(), ()>
 }
 
 pub async fn start_server() -> SocketAddr {
+    return start_server_with_resolver(Arc::new(NoResolver)).await;
+}
+
+pub async fn start_server_with_resolver(resolver: Arc<dyn Resolver>) -> SocketAddr {
     let listener = match TcpListener::bind("127.0.0.1:0").await {
         Ok(l) => l,
         Err(e) => panic!("{}", e),
     };
     let server_addr = listener.local_addr().unwrap();
     tokio::spawn(async move {
-        run(listener, tokio::signal::ctrl_c()).await
+        run(listener, Vec::new(), resolver, tokio::signal::ctrl_c()).await
     });
 
     return server_addr;
 }
 
+pub async fn start_websocket_server(origins: Vec<String>) -> SocketAddr {
+    let listener = match TcpListener::bind("127.0.0.1:0").await {
+        Ok(l) => l,
+        Err(e) => panic!("{}", e),
+    };
+    let websocket = match TcpListener::bind("127.0.0.1:0").await {
+        Ok(l) => l,
+        Err(e) => panic!("{}", e),
+    };
+    let websocket_addr = websocket.local_addr().unwrap();
+    let acceptor = WebSocketAcceptor::new(None, origins);
+    tokio::spawn(async move {
+        run(listener, vec![(websocket, acceptor)], Arc::new(NoResolver), tokio::signal::ctrl_c()).await
+    });
+
+    return websocket_addr;
+}
@@ -0,0 +1,70 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// The server notices an operator with user mode +s receives.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Snomask: u8 {
+        /// c: clients connecting and exiting.
+        const CONNECTS = 0b00000001;
+        /// n: nickname changes.
+        const NICKS = 0b00000010;
+        /// k: users disconnected by the server.
+        const KILLS = 0b00000100;
+        /// b: server bans being added and removed.
+        const BANS = 0b00001000;
+        /// f: excess flood disconnects and connection throttling.
+        const FLOOD = 0b00010000;
+        /// r: connections refused before or during registration.
+        const REJECTS = 0b00100000;
+        /// S: spam filter hits and changes.
+        const SPAMFILTER = 0b01000000;
+        /// F: clients connecting and exiting on other servers. Nothing is
+        /// sent for it until servers can be linked.
+        const FAR_CONNECTS = 0b10000000;
+    }
+}
+
+/// Every snomask by letter, in the order they are shown.
+pub const SNOMASKS: [(char, Snomask); 8] = [
+    ('F', Snomask::FAR_CONNECTS),
+    ('S', Snomask::SPAMFILTER),
+    ('b', Snomask::BANS),
+    ('c', Snomask::CONNECTS),
+    ('f', Snomask::FLOOD),
+    ('k', Snomask::KILLS),
+    ('n', Snomask::NICKS),
+    ('r', Snomask::REJECTS),
+];
+
+impl Snomask {
+    pub fn letters(&self) -> String {
+        return SNOMASKS.iter()
+            .filter(|(_, snomask)| self.contains(*snomask))
+            .map(|(letter, _)| *letter)
+            .collect();
+    }
+
+    /// The first letter of a change such as `+cn-k` that is not a snomask.
+    pub fn unknown_letter(change: &str) -> Option<char> {
+        return change.chars()
+            .find(|letter| *letter != '+' && *letter != '-' && !SNOMASKS.iter().any(|(known, _)| known == letter));
+    }
+
+    /// Applies a change such as `+cn-k`, where a change without a leading
+    /// sign adds. Unknown letters are ignored.
+    pub fn apply(&self, change: &str) -> Snomask {
+        let mut snomask = *self;
+        let mut adding = true;
+        for letter in change.chars() {
+            match letter {
+                '+' | '-' => adding = letter == '+',
+                _ => {
+                    if let Some((_, flag)) = SNOMASKS.iter().find(|(known, _)| *known == letter) {
+                        snomask.set(*flag, adding);
+                    }
+                },
+            }
+        }
+        return snomask;
+    }
+}
@@ -0,0 +1,214 @@
+use std::fs::{read_to_string, write};
+use std::sync::{Arc, Mutex};
+
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::CONFIG;
+use crate::mask;
+
+pub static SPAMFILTERS: Lazy<Arc<Mutex<SpamFilterList>>> = Lazy::new(|| {
+    let path = CONFIG.lock().unwrap().server.spamfilter_file.clone();
+    Arc::new(Mutex::new(SpamFilterList::load(path)))
+});
+
+/// The kind of text a spam filter is run against.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FilterTarget {
+    Private,
+    Channel,
+    Part,
+    Quit,
+    Topic,
+}
+
+impl FilterTarget {
+    pub fn letter(&self) -> char {
+        match self {
+            FilterTarget::Private => 'p',
+            FilterTarget::Channel => 'c',
+            FilterTarget::Part => 'P',
+            FilterTarget::Quit => 'q',
+            FilterTarget::Topic => 't',
+        }
+    }
+
+    /// Parses a set of target letters like `pc`.
+    pub fn parse(letters: &str) -> Option<Vec<FilterTarget>> {
+        let mut targets = Vec::new();
+        for letter in letters.chars() {
+            let target = match letter {
+                'p' => FilterTarget::Private,
+                'c' => FilterTarget::Channel,
+                'P' => FilterTarget::Part,
+                'q' => FilterTarget::Quit,
+                't' => FilterTarget::Topic,
+                _ => return None,
+            };
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        if targets.is_empty() {
+            return None;
+        }
+        return Some(targets);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FilterAction {
+    /// Drops the text and tells the sender.
+    Block,
+    /// Lets the text through and only notifies opers.
+    Warn,
+    /// Disconnects the sender.
+    Kill,
+    /// Places a G-line on the sender's address.
+    Gline,
+    /// Places a shun on the sender's address and drops the text.
+    Shun,
+}
+
+impl FilterAction {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "block" => Some(FilterAction::Block),
+            "warn" => Some(FilterAction::Warn),
+            "kill" => Some(FilterAction::Kill),
+            "gline" => Some(FilterAction::Gline),
+            "shun" => Some(FilterAction::Shun),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterAction::Block => "block",
+            FilterAction::Warn => "warn",
+            FilterAction::Kill => "kill",
+            FilterAction::Gline => "gline",
+            FilterAction::Shun => "shun",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpamFilter {
+    /// A wildcard or `/regex/` mask, matched against the whole text.
+    pub mask: String,
+    /// `mask` compiled once, when it is a `/regex/`.
+    #[serde(skip)]
+    regex: Option<Regex>,
+    pub targets: Vec<FilterTarget>,
+    pub action: FilterAction,
+    /// How many seconds a G-line or shun placed by this filter lasts,
+    /// forever when `None`.
+    pub duration: Option<u64>,
+    pub reason: String,
+    pub setter: String,
+    #[serde(default)]
+    pub hits: u64,
+}
+
+impl SpamFilter {
+    pub fn new(mask: String, targets: Vec<FilterTarget>, action: FilterAction, duration: Option<u64>, reason: String, setter: String) -> Self {
+        let regex = mask::compile_pattern(&mask);
+        return SpamFilter { mask, regex, targets, action, duration, reason, setter, hits: 0 };
+    }
+
+    pub fn matches(&self, target: FilterTarget, text: &str) -> bool {
+        if !self.targets.contains(&target) {
+            return false;
+        }
+        if mask::is_regex(&self.mask) {
+            return self.regex.as_ref().is_some_and(|regex| regex.is_match(text));
+        }
+        return mask::matches(&self.mask, text);
+    }
+
+    pub fn target_letters(&self) -> String {
+        return self.targets.iter().map(FilterTarget::letter).collect();
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SpamFilterFile {
+    #[serde(default)]
+    spamfilter: Vec<SpamFilter>,
+}
+
+/// Every spam filter, written back to `path` whenever a filter is added or
+/// removed.
+pub struct SpamFilterList {
+    path: Option<String>,
+    filters: Vec<SpamFilter>,
+}
+
+impl SpamFilterList {
+    pub fn load(path: Option<String>) -> Self {
+        let mut filters = match &path {
+            Some(path) => match read_to_string(path) {
+                Ok(contents) => match toml::from_str::<SpamFilterFile>(&contents) {
+                    Ok(file) => file.spamfilter,
+                    Err(err) => {
+                        warn!("Could not load spam filters from file \"{}\": {}", path, err);
+                        Vec::new()
+                    },
+                },
+                Err(_) => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+        for filter in filters.iter_mut() {
+            filter.regex = mask::compile_pattern(&filter.mask);
+        }
+        info!("Loaded {} spam filters", filters.len());
+        return SpamFilterList { path, filters };
+    }
+
+    /// Adds `filter`, replacing any filter on the same mask.
+    pub fn add(&mut self, filter: SpamFilter) {
+        self.filters.retain(|existing| !existing.mask.eq_ignore_ascii_case(&filter.mask));
+        self.filters.push(filter);
+        self.save();
+    }
+
+    pub fn remove(&mut self, mask: &str) -> bool {
+        let count = self.filters.len();
+        self.filters.retain(|filter| !filter.mask.eq_ignore_ascii_case(mask));
+        if self.filters.len() == count {
+            return false;
+        }
+        self.save();
+        return true;
+    }
+
+    pub fn list(&self) -> Vec<SpamFilter> {
+        return self.filters.clone();
+    }
+
+    /// The first filter matching `text` sent as `target`, counting the hit.
+    pub fn check(&mut self, target: FilterTarget, text: &str) -> Option<SpamFilter> {
+        let filter = self.filters.iter_mut().find(|filter| filter.matches(target, text))?;
+        filter.hits += 1;
+        return Some(filter.clone());
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let file = SpamFilterFile { spamfilter: self.filters.clone() };
+        match toml::to_string(&file) {
+            Ok(contents) => {
+                if let Err(err) = write(path, contents) {
+                    warn!("Could not write spam filters to file \"{}\": {}", path, err);
+                }
+            },
+            Err(err) => warn!("Could not serialize spam filters: {}", err),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+
+use tokio::time::{Duration, Instant};
+
+use crate::config::CONFIG;
+use crate::mask;
+
+pub enum Throttled {
+    /// The address just went over the limit and has been banned.
+    New,
+    /// The address is still serving an earlier ban.
+    Active,
+}
+
+/// Per address connection rate limiting, shared by all listeners.
+#[derive(Default)]
+pub struct Throttler {
+    attempts: HashMap<IpAddr, VecDeque<Instant>>,
+    bans: HashMap<IpAddr, Instant>,
+    pub refused: u64,
+    pub banned: u64,
+}
+
+impl Throttler {
+    /// Records a connection from `ip_address`. Addresses making more than
+    /// `throttle.connections` connections within `throttle.period` seconds
+    /// are refused for `throttle.ban_duration` seconds.
+    pub fn connect(&mut self, ip_address: IpAddr) -> Result<(), Throttled> {
+        let config = CONFIG.lock().unwrap().throttle.clone();
+        if config.exempt.iter().any(|host| mask::matches_address(host, ip_address)) {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        self.bans.retain(|_, until| *until > now);
+        if self.bans.contains_key(&ip_address) {
+            self.refused += 1;
+            return Err(Throttled::Active);
+        }
+
+        let period = Duration::from_secs(config.period);
+        self.attempts.retain(|_, attempts| attempts.back().is_some_and(|last| now.duration_since(*last) < period));
+        let attempts = self.attempts.entry(ip_address).or_default();
+        while attempts.front().is_some_and(|first| now.duration_since(*first) >= period) {
+            attempts.pop_front();
+        }
+        attempts.push_back(now);
+
+        if attempts.len() > config.connections {
+            self.attempts.remove(&ip_address);
+            self.bans.insert(ip_address, now + Duration::from_secs(config.ban_duration));
+            self.refused += 1;
+            self.banned += 1;
+            return Err(Throttled::New);
+        }
+        return Ok(());
+    }
+
+    pub fn active_bans(&self) -> usize {
+        let now = Instant::now();
+        return self.bans.values().filter(|until| **until > now).count();
+    }
+}
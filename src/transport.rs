@@ -0,0 +1,61 @@
+use std::net::SocketAddr;
+
+use irc_proto::connection::Connection;
+use irc_proto::types::{Command, Message};
+
+use crate::websocket::WebSocketConnection;
+
+/// The client side of a `Handler`, whichever listener accepted it.
+pub enum Transport {
+    Tcp(Connection),
+    WebSocket(Box<WebSocketConnection>),
+}
+
+impl Transport {
+    pub fn address(&self) -> SocketAddr {
+        match self {
+            Transport::Tcp(connection) => connection.address(),
+            Transport::WebSocket(connection) => connection.address(),
+        }
+    }
+
+    pub fn is_secure(&self) -> bool {
+        match self {
+            Transport::Tcp(_) => false,
+            Transport::WebSocket(connection) => connection.is_secure(),
+        }
+    }
+
+    pub async fn read(&mut self) -> Result<Message, ()> {
+        match self {
+            Transport::Tcp(connection) => connection.read().await,
+            Transport::WebSocket(connection) => connection.read().await,
+        }
+    }
+
+    pub async fn write(&mut self, message: Message) -> Result<(), ()> {
+        match self {
+            Transport::Tcp(connection) => connection.write(message).await,
+            Transport::WebSocket(connection) => connection.write(message).await,
+        }
+    }
+
+    pub async fn shutdown(&mut self) {
+        match self {
+            Transport::Tcp(connection) => connection.shutdown().await,
+            Transport::WebSocket(connection) => connection.shutdown().await,
+        }
+    }
+
+    /// Tells the client why it is being dropped and closes the connection.
+    pub async fn close_link(&mut self, reason: &str) {
+        let _ = self.write(Message {
+            tags: None,
+            source: None,
+            command: Command::ERROR {
+                message: format!("Closing Link ({})", reason),
+            },
+        }).await;
+        self.shutdown().await;
+    }
+}
@@ -1,4 +1,9 @@
+use std::net::IpAddr;
 use bitflags::bitflags;
+use irc_proto::types::Source;
+
+use crate::mask;
+use crate::snomask::Snomask;
 
 bitflags! {
     #[derive(Debug, Clone)]
@@ -7,27 +12,155 @@ bitflags! {
         const PASS = 0b00000001;
         const NICK = 0b00000010;
         const USER = 0b00000100;
+        /// Capability negotiation is in progress and holds up registration
+        /// until CAP END.
+        const CAP = 0b00001000;
     }
 }
 
+bitflags! {
+    /// IRCv3 capabilities a client has enabled with CAP REQ.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Capabilities: u8 {
+        const AWAY_NOTIFY = 0b00000001;
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct UserModes: u8 {
+        /// +i: hidden from WHO for anyone not sharing a channel.
+        const INVISIBLE = 0b00000001;
+        /// +w: receives WALLOPS.
+        const WALLOPS = 0b00000010;
+        /// +o: an IRC operator. Only OPER sets it; users may only drop it.
+        const OPER = 0b00000100;
+        /// +B: marked as a bot in WHO and WHOIS.
+        const BOT = 0b00001000;
+        /// +R: only accepts private messages from authenticated users.
+        const REGISTERED_ONLY = 0b00010000;
+        /// +x: shown with the cloaked host.
+        const CLOAKED = 0b00100000;
+        /// +s: receives server notices, picked with a snomask.
+        const SNOTICE = 0b01000000;
+    }
+}
+
+/// Every user mode by letter, in the order they are shown.
+pub const USER_MODES: [(char, UserModes); 7] = [
+    ('B', UserModes::BOT),
+    ('R', UserModes::REGISTERED_ONLY),
+    ('i', UserModes::INVISIBLE),
+    ('o', UserModes::OPER),
+    ('s', UserModes::SNOTICE),
+    ('w', UserModes::WALLOPS),
+    ('x', UserModes::CLOAKED),
+];
+
+/// Every capability the server offers, by the name used in CAP.
+pub const CAPABILITIES: [(&str, Capabilities); 1] = [
+    ("away-notify", Capabilities::AWAY_NOTIFY),
+];
+
+/// The longest away message kept; longer ones are truncated.
+pub const AWAYLEN: usize = 200;
+
 #[derive(Debug, Clone)]
 pub struct User {
     pub username: String,
     pub nickname: String,
     pub realname: String,
     pub hostname: String,
+    pub cloaked_host: String,
+    pub vhost: Option<String>,
+    pub modes: UserModes,
+    pub ip_address: IpAddr,
+    pub gateway: Option<String>,
+    pub secure: bool,
+    /// The oper block the user logged in with, set along with +o.
+    pub oper: Option<String>,
+    /// The server notices received with +s.
+    pub snomask: Snomask,
+    pub class: String,
+    pub capabilities: Capabilities,
+    /// The away message, set while the user is away.
+    pub away: Option<String>,
+    /// When the user registered, in seconds since the Unix epoch.
+    pub signon: u64,
+    /// When the user last sent a message, in seconds since the Unix epoch.
+    pub idle_since: u64,
     pub register_state: RegistrationFlags,
 }
 
 
 impl User {
-    pub fn new() -> Self {
+    pub fn new(ip_address: IpAddr) -> Self {
         return User{
             username: String::new(),
             nickname: String::new(),
             realname: String::new(),
-            hostname: String::new(),
+            hostname: ip_address.to_string(),
+            cloaked_host: String::new(),
+            vhost: None,
+            modes: UserModes::empty(),
+            ip_address,
+            gateway: None,
+            secure: false,
+            oper: None,
+            snomask: Snomask::empty(),
+            class: String::new(),
+            capabilities: Capabilities::empty(),
+            away: None,
+            signon: 0,
+            idle_since: 0,
             register_state: RegistrationFlags::NONE,
         }
     }
+
+    pub fn is_registered(&self) -> bool {
+        return self.register_state.contains(RegistrationFlags::PASS | RegistrationFlags::NICK | RegistrationFlags::USER)
+            && !self.register_state.contains(RegistrationFlags::CAP);
+    }
+
+    /// The host other users see: the vhost if there is one, the cloaked host
+    /// with mode +x and the real hostname otherwise.
+    pub fn visible_host(&self) -> &str {
+        if let Some(vhost) = &self.vhost {
+            return vhost;
+        }
+        if self.modes.contains(UserModes::CLOAKED) {
+            return &self.cloaked_host;
+        }
+        return &self.hostname;
+    }
+
+    /// The `nick!user@host` prefix of messages from this user.
+    pub fn source(&self) -> Source {
+        return Source {
+            name: self.nickname.clone(),
+            user: Some(self.username.clone()),
+            host: Some(self.visible_host().to_string()),
+        };
+    }
+
+    pub fn mode_string(&self) -> String {
+        let letters: String = USER_MODES.iter()
+            .filter(|(_, mode)| self.modes.contains(*mode))
+            .map(|(letter, _)| *letter)
+            .collect();
+        return format!("+{}", letters);
+    }
+
+    pub fn matches_host(&self, host_mask: &str) -> bool {
+        return mask::matches(host_mask, &self.hostname)
+            || mask::matches_address(host_mask, self.ip_address);
+    }
+
+    /// Like `matches_host`, but also tries the cloaked host and the vhost, so
+    /// bans placed on what other users see still apply.
+    pub fn matches_any_host(&self, host_mask: &str) -> bool {
+        return self.matches_host(host_mask)
+            || (!self.cloaked_host.is_empty() && mask::matches(host_mask, &self.cloaked_host))
+            || self.vhost.as_ref().is_some_and(|vhost| mask::matches(host_mask, vhost));
+    }
 }
@@ -0,0 +1,192 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use log::{info, warn};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::{rustls, TlsAcceptor};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message as Frame;
+use tokio_tungstenite::WebSocketStream;
+
+use irc_proto::types::Message;
+
+use crate::config::WebSocket;
+use crate::mask;
+
+const TEXT_PROTOCOL: &str = "text.ircv3.net";
+const BINARY_PROTOCOL: &str = "binary.ircv3.net";
+/// The largest frame or message a client may send: a 512 byte line with up
+/// to 8191 bytes of message tags.
+const MAX_MESSAGE_SIZE: usize = 512 + 8191;
+
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Stream for T {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameKind {
+    Text,
+    Binary,
+}
+
+/// Performs the TLS and WebSocket handshakes for one WebSocket listener.
+pub struct WebSocketAcceptor {
+    tls: Option<TlsAcceptor>,
+    origins: Vec<String>,
+}
+
+impl WebSocketAcceptor {
+    /// An empty `origins` list accepts every origin. Otherwise a browser's
+    /// `Origin` header has to match one of the masks; clients that send no
+    /// `Origin` at all are not browsers and are let through.
+    pub fn new(tls: Option<TlsAcceptor>, origins: Vec<String>) -> Self {
+        return WebSocketAcceptor { tls, origins }
+    }
+
+    pub fn from_config(config: &WebSocket) -> Result<Self, String> {
+        let tls = match &config.tls {
+            Some(tls) => Some(load_tls(&tls.certificate, &tls.key)?),
+            None => None,
+        };
+        return Ok(WebSocketAcceptor::new(tls, config.origins.clone()));
+    }
+
+    pub async fn accept(&self, stream: TcpStream, address: SocketAddr) -> Result<WebSocketConnection, ()> {
+        let (stream, secure): (Box<dyn Stream>, bool) = match &self.tls {
+            Some(tls) => match tls.accept(stream).await {
+                Ok(stream) => (Box::new(stream), true),
+                Err(err) => {
+                    info!("{:} TLS handshake failed: {}", address, err);
+                    return Err(());
+                },
+            },
+            None => (Box::new(stream), false),
+        };
+
+        let mut kind = FrameKind::Text;
+        #[allow(clippy::result_large_err)]
+        let callback = |request: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+            if let Some(origin) = request.headers().get("Origin") {
+                let origin = origin.to_str().unwrap_or_default();
+                if !self.origins.is_empty() && !self.origins.iter().any(|allowed| mask::matches(allowed, origin)) {
+                    let mut error = ErrorResponse::new(Some("Origin not allowed".to_string()));
+                    *error.status_mut() = StatusCode::FORBIDDEN;
+                    return Err(error);
+                }
+            }
+
+            let offered = request.headers().get_all("Sec-WebSocket-Protocol").iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .map(|protocol| protocol.trim())
+                .find(|protocol| *protocol == TEXT_PROTOCOL || *protocol == BINARY_PROTOCOL);
+            if let Some(protocol) = offered {
+                let protocol = if protocol == BINARY_PROTOCOL {
+                    kind = FrameKind::Binary;
+                    BINARY_PROTOCOL
+                } else {
+                    TEXT_PROTOCOL
+                };
+                response.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static(protocol));
+            }
+            return Ok(response);
+        };
+
+        let config = WebSocketConfig::default()
+            .max_frame_size(Some(MAX_MESSAGE_SIZE))
+            .max_message_size(Some(MAX_MESSAGE_SIZE));
+        match tokio_tungstenite::accept_hdr_async_with_config(stream, callback, Some(config)).await {
+            Ok(stream) => Ok(WebSocketConnection {
+                stream,
+                address,
+                kind,
+                secure,
+                pending: VecDeque::new(),
+            }),
+            Err(err) => {
+                info!("{:} WebSocket handshake failed: {}", address, err);
+                Err(())
+            },
+        }
+    }
+}
+
+/// A client speaking IRC over WebSocket, one IRC line per frame.
+pub struct WebSocketConnection {
+    stream: WebSocketStream<Box<dyn Stream>>,
+    address: SocketAddr,
+    kind: FrameKind,
+    secure: bool,
+    pending: VecDeque<Message>,
+}
+
+impl WebSocketConnection {
+    pub fn address(&self) -> SocketAddr {
+        return self.address;
+    }
+
+    pub fn is_secure(&self) -> bool {
+        return self.secure;
+    }
+
+    pub async fn read(&mut self) -> Result<Message, ()> {
+        loop {
+            if let Some(message) = self.pending.pop_front() {
+                return Ok(message);
+            }
+
+            let text = match self.stream.next().await {
+                Some(Ok(Frame::Text(text))) => text.to_string(),
+                Some(Ok(Frame::Binary(data))) => String::from_utf8_lossy(&data).into_owned(),
+                Some(Ok(Frame::Close(_))) | Some(Err(_)) | None => return Err(()),
+                Some(Ok(_)) => continue,
+            };
+            for line in text.split(['\r', '\n']).filter(|line| !line.is_empty()) {
+                match line.parse::<Message>() {
+                    Ok(message) => self.pending.push_back(message),
+                    Err(_) => warn!("{:} sent invalid message", self.address),
+                }
+            }
+        }
+    }
+
+    pub async fn write(&mut self, message: Message) -> Result<(), ()> {
+        let line = message.to_bytes();
+        let line = line.trim_end_matches(['\r', '\n']);
+        let frame = match self.kind {
+            FrameKind::Text => Frame::text(line),
+            FrameKind::Binary => Frame::binary(line.as_bytes().to_vec()),
+        };
+        return self.stream.send(frame).await.map_err(|_| ());
+    }
+
+    pub async fn shutdown(&mut self) {
+        let _ = self.stream.close(None).await;
+    }
+}
+
+fn load_tls(certificate: &str, key: &str) -> Result<TlsAcceptor, String> {
+    let mut reader = BufReader::new(File::open(certificate)
+        .map_err(|err| format!("Could not read file \"{}\": {}", certificate, err))?);
+    let certificates = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("Could not load certificates from \"{}\": {}", certificate, err))?;
+
+    let mut reader = BufReader::new(File::open(key)
+        .map_err(|err| format!("Could not read file \"{}\": {}", key, err))?);
+    let key = rustls_pemfile::private_key(&mut reader)
+        .map_err(|err| format!("Could not load private key from \"{}\": {}", key, err))?
+        .ok_or(format!("No private key in \"{}\"", key))?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)
+        .map_err(|err| err.to_string())?;
+    return Ok(TlsAcceptor::from(Arc::new(config)));
+}
@@ -0,0 +1,113 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use irc_proto::types::{Command, Message};
+use tokio::{io::AsyncReadExt, io::AsyncWriteExt, net::TcpStream, time::sleep};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use irc_server::resolver::{Lookup, Resolver};
+
+
+/// Connects and reads the notice every new connection starts with.
+pub async fn connect(server_addr: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(server_addr).await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE * :*** Looking up your hostname...",
+        read_line(&mut stream).await
+    );
+    return stream;
+}
+
+/// Sends PASS, NICK and USER.
+pub async fn write_registration(stream: &mut TcpStream, nickname: &str) {
+    stream.write_all(
+        Message{
+            tags: None,
+            source: None,
+            command: Command::PASS { password: "password".to_string() }
+        }.to_bytes().as_bytes()
+    ).await.unwrap();
+
+    stream.write_all(
+        Message{
+            tags: None,
+            source: None,
+            command: Command::NICK { nickname: nickname.to_string() }
+        }.to_bytes().as_bytes()
+    ).await.unwrap();
+
+    stream.write_all(
+        Message{
+            tags: None,
+            source: None,
+            command: Command::USER {
+                user: nickname.to_string(),
+                mode: "0".to_string(),
+                unused: "*".to_string(),
+                realname: nickname.to_string(),
+            }
+        }.to_bytes().as_bytes()
+    ).await.unwrap();
+}
+
+/// Sends PASS, NICK and USER and reads the hostname notice.
+pub async fn send_registration(stream: &mut TcpStream, nickname: &str) {
+    write_registration(stream, nickname).await;
+    assert_eq!(
+        format!(":server1 NOTICE {} :*** Couldn't look up your hostname", nickname),
+        read_line(stream).await
+    );
+}
+
+pub async fn register(stream: &mut TcpStream, nickname: String) {
+    send_registration(stream, &nickname).await;
+    read_welcome(stream, &nickname).await;
+}
+
+/// Reads the numerics sent on registration, up to the end of the MOTD.
+pub async fn read_welcome(stream: &mut TcpStream, nickname: &str) {
+    assert!(read_line(stream).await.starts_with(&format!(":server1 001 {} ", nickname)));
+    loop {
+        let line = read_line(stream).await;
+        if line.starts_with(&format!(":server1 376 {} ", nickname)) || line.starts_with(&format!(":server1 422 {} ", nickname)) {
+            break;
+        }
+    }
+}
+
+pub async fn read_line(stream: &mut TcpStream) -> String {
+    let mut line = Vec::new();
+    while !line.ends_with(b"\r\n") {
+        line.push(stream.read_u8().await.unwrap());
+    }
+    line.truncate(line.len() - 2);
+    return String::from_utf8(line).unwrap();
+}
+
+/// Answers lookups from fixed tables, IPv4 ones after `delay`.
+#[derive(Default)]
+pub struct StubResolver {
+    pub ipv4: HashMap<String, Ipv4Addr>,
+    pub delay: Duration,
+    pub forward: HashMap<String, IpAddr>,
+    pub ptr: HashMap<IpAddr, String>,
+}
+
+impl Resolver for StubResolver {
+    fn lookup_ipv4(&self, name: String) -> Lookup<'_, Vec<Ipv4Addr>> {
+        return Box::pin(async move {
+            sleep(self.delay).await;
+            return self.ipv4.get(&name).into_iter().cloned().collect();
+        });
+    }
+
+    fn lookup_ip(&self, name: String) -> Lookup<'_, Vec<IpAddr>> {
+        return Box::pin(async move { self.forward.get(&name).into_iter().cloned().collect() });
+    }
+
+    fn lookup_ptr(&self, ip_address: IpAddr) -> Lookup<'_, Vec<String>> {
+        return Box::pin(async move { self.ptr.get(&ip_address).into_iter().cloned().collect() });
+    }
+}
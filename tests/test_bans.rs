@@ -0,0 +1,39 @@
+use std::env::temp_dir;
+use std::fs::remove_file;
+use std::net::IpAddr;
+
+use irc_server::ban::{Ban, BanKind, BanList};
+use irc_server::user::User;
+
+
+#[test]
+fn test_ban_file() {
+    let path = temp_dir().join("irc_server_test_bans.toml").to_string_lossy().to_string();
+    let _ = remove_file(&path);
+
+    let mut bans = BanList::load(Some(path.clone()));
+    bans.add(Ban::new(BanKind::Kline, "*@*.spam.example.com".to_string(), "spam".to_string(), "nick1".to_string(), None));
+    bans.add(Ban::new(BanKind::Dline, "192.0.2.0/24".to_string(), "abuse".to_string(), "nick1".to_string(), Some(3600)));
+    bans.add(Ban::new(BanKind::Qline, "/^guest[0-9]+$/".to_string(), "reserved".to_string(), "nick1".to_string(), None));
+
+    let bans = BanList::load(Some(path.clone()));
+    let klines = bans.list(BanKind::Kline);
+    assert_eq!(1, klines.len());
+    assert_eq!("*@*.spam.example.com", klines[0].mask);
+    assert_eq!("nick1", klines[0].setter);
+
+    let address: IpAddr = "192.0.2.77".parse().unwrap();
+    assert_eq!("abuse", bans.find_address(address).unwrap().reason);
+    assert!(bans.find_address("198.51.100.1".parse().unwrap()).is_none());
+
+    let mut user = User::new("198.51.100.1".parse().unwrap());
+    user.username = "spammer".to_string();
+    user.hostname = "host.spam.example.com".to_string();
+    assert_eq!("spam", bans.find_user(&user).unwrap().reason);
+
+    // Regular expressions are compiled again when the file is loaded.
+    assert_eq!("reserved", bans.find_nickname("Guest42").unwrap().reason);
+    assert!(bans.find_nickname("guest").is_none());
+
+    remove_file(&path).unwrap();
+}
@@ -4,46 +4,21 @@ use log::info;
 use std::time::{Duration, Instant};
 use serial_test::serial;
 
+use irc_server::ban::{Ban, BanKind, BANS};
+use irc_server::cloak::cloak;
+use irc_server::config::{Vhost, CONFIG};
 use irc_server::server::start_server;
 
+mod common;
+use common::{connect, read_line, read_welcome, register, send_registration};
 
-async fn register(stream: &mut TcpStream, nickname: String) {
-    stream.write_all(
-        Message{
-            tags: None,
-            source: None,
-            command: Command::PASS { password: "password".to_string() }
-        }.to_bytes().as_bytes()
-    ).await.unwrap();
-
-    stream.write_all(
-        Message{
-            tags: None,
-            source: None,
-            command: Command::NICK { nickname: nickname.clone() }
-        }.to_bytes().as_bytes()
-    ).await.unwrap();
-
-    stream.write_all(
-        Message{
-            tags: None,
-            source: None,
-            command: Command::USER {
-                user: nickname.clone(),
-                mode: "0".to_string(),
-                unused: "*".to_string(),
-                realname: nickname.clone(),
-            }
-        }.to_bytes().as_bytes()
-    ).await.unwrap();
-}
 
 #[serial]
 #[tokio::test]
 async fn test_ping() {
     enable_logging();
     let addr = start_server().await;
-    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = connect(addr).await;
 
     let now = Instant::now();
 
@@ -64,7 +39,7 @@ async fn test_ping() {
 #[tokio::test]
 async fn test_ping_multiple() {
     let addr = start_server().await;
-    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = connect(addr).await;
 
     stream.write_all(b"PING token1\r\nPING token2\r\n").await.unwrap();
     let mut response = [0; 22];
@@ -86,7 +61,7 @@ async fn test_ping_multiple() {
 #[tokio::test]
 async fn test_invalid_message() {
     let addr = start_server().await;
-    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = connect(addr).await;
 
     stream.write_all(b"PING token1\r\nINVALID\r\nPING token2\r\n").await.unwrap();
     let mut response = [0; 22];
@@ -108,7 +83,7 @@ async fn test_invalid_message() {
 #[tokio::test]
 async fn test_partial() {
     let addr = start_server().await;
-    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut stream = connect(addr).await;
 
     stream.write_all(b"PING ").await.unwrap();
     sleep(Duration::from_secs(1)).await;
@@ -126,10 +101,10 @@ async fn test_partial() {
 async fn test_message() {
     let server_addr = start_server().await;
 
-    let mut client1 = TcpStream::connect(server_addr).await.unwrap();
+    let mut client1 = connect(server_addr).await;
     register(&mut client1, "nick1".to_string()).await;
 
-    let mut client2 = TcpStream::connect(server_addr).await.unwrap();
+    let mut client2 = connect(server_addr).await;
     register(&mut client2, "nick2".to_string()).await;
 
     tokio::time::sleep(tokio::time::Duration::from_micros(1)).await;
@@ -145,10 +120,10 @@ async fn test_message() {
         }.to_bytes().as_bytes()
     ).await.unwrap();
 
-    let mut response = [0; 28];
+    let mut response = [0; 44];
     client2.read(&mut response).await.unwrap();
     assert_eq!(
-        ":nick1 PRIVMSG nick2 hello\r\n".as_bytes(),
+        ":nick1!nick1@127.0.0.1 PRIVMSG nick2 hello\r\n".as_bytes(),
         &response
     );
 }
@@ -160,10 +135,10 @@ async fn test_channel() {
     let server_addr = start_server().await;
     let now = Instant::now();
 
-    let mut client1 = TcpStream::connect(server_addr).await.unwrap();
+    let mut client1 = connect(server_addr).await;
     register(&mut client1, "nick1".to_string()).await;
 
-    let mut client2 = TcpStream::connect(server_addr).await.unwrap();
+    let mut client2 = connect(server_addr).await;
     register(&mut client2, "nick2".to_string()).await;
 
     client1.write_all(
@@ -176,10 +151,10 @@ async fn test_channel() {
             }
         }.to_bytes().as_bytes()
     ).await.unwrap();
-    let mut response = [0; 23];
+    let mut response = [0; 39];
     client1.read(&mut response).await.unwrap();
     assert_eq!(
-        ":nick1 JOIN #channel1\r\n".as_bytes(),
+        ":nick1!nick1@127.0.0.1 JOIN #channel1\r\n".as_bytes(),
         &response
     );
 
@@ -193,10 +168,10 @@ async fn test_channel() {
             }
         }.to_bytes().as_bytes()
     ).await.unwrap();
-    let mut response = [0; 23];
+    let mut response = [0; 39];
     client2.read(&mut response).await.unwrap();
     assert_eq!(
-        ":nick2 JOIN #channel1\r\n".as_bytes(),
+        ":nick2!nick2@127.0.0.1 JOIN #channel1\r\n".as_bytes(),
         &response
     );
 
@@ -210,12 +185,1183 @@ async fn test_channel() {
             }
         }.to_bytes().as_bytes()
     ).await.unwrap();
-    let mut response = [0; 32];
+    let mut response = [0; 48];
     client2.read(&mut response).await.unwrap();
     assert_eq!(
-        ":nick1 PRIVMSG #channel1 hello\r\n".as_bytes(),
+        ":nick1!nick1@127.0.0.1 PRIVMSG #channel1 hello\r\n".as_bytes(),
         &response
     );
     let elapsed = now.elapsed();
     info!("Elapsed: {:.4?}", elapsed);
 }
+
+#[serial]
+#[tokio::test]
+async fn test_webirc_invalid_password() {
+    let server_addr = start_server().await;
+    let mut client = connect(server_addr).await;
+
+    client.write_all(
+        Message{
+            tags: None,
+            source: None,
+            command: Command::WEBIRC {
+                password: "wrong".to_string(),
+                gateway: "webchat".to_string(),
+                hostname: "client.example.com".to_string(),
+                ip: "192.0.2.1".to_string(),
+                options: None,
+            }
+        }.to_bytes().as_bytes()
+    ).await.unwrap();
+
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    assert_eq!(
+        "ERROR :Closing Link (WEBIRC: Invalid gateway credentials)\r\n".as_bytes(),
+        &response
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_webirc() {
+    let server_addr = start_server().await;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    oper.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut oper).await;
+
+    let webirc = Message{
+        tags: None,
+        source: None,
+        command: Command::WEBIRC {
+            password: "webpassword".to_string(),
+            gateway: "webchat".to_string(),
+            hostname: "client.example.com".to_string(),
+            ip: "192.0.2.1".to_string(),
+            options: None,
+        }
+    }.to_bytes();
+    let mut client = connect(server_addr).await;
+    client.write_all(webirc.as_bytes()).await.unwrap();
+    client.write_all(b"PASS password\r\nNICK nick2\r\nUSER nick2 0 * nick2\r\n").await.unwrap();
+    read_welcome(&mut client, "nick2").await;
+
+    oper.write_all(b"WHOIS nick2\r\n").await.unwrap();
+    assert_eq!(":server1 311 nick1 nick2 nick2 client.example.com * nick2", read_line(&mut oper).await);
+    assert_eq!(
+        ":server1 378 nick1 nick2 :is connecting from *@client.example.com 192.0.2.1",
+        read_line(&mut oper).await
+    );
+    read_line(&mut oper).await;
+    assert_eq!(":server1 320 nick1 nick2 :is in connection class users", read_line(&mut oper).await);
+    read_line(&mut oper).await;
+    read_line(&mut oper).await;
+
+    // K-lines apply to the address the gateway gave.
+    oper.write_all(b"KLINE *@192.0.2.1 :spoofed\r\n").await.unwrap();
+    read_line(&mut oper).await;
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    BANS.lock().unwrap().remove(BanKind::Kline, "*@192.0.2.1");
+    assert_eq!("ERROR :Closing Link (K-lined: spoofed)\r\n".as_bytes(), &response);
+
+    // So does reconnect throttling, rather than to the gateway.
+    CONFIG.lock().unwrap().throttle.connections = 1;
+    CONFIG.lock().unwrap().throttle.exempt = vec!["127.0.0.1".to_string()];
+    let mut client = connect(server_addr).await;
+    client.write_all(webirc.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    CONFIG.lock().unwrap().throttle.connections = 10;
+    CONFIG.lock().unwrap().throttle.exempt = Vec::new();
+    assert_eq!("ERROR :Closing Link (Throttled: Reconnecting too fast)\r\n".as_bytes(), &response);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_registration_timeout() {
+    CONFIG.lock().unwrap().server.registration_timeout = 1;
+    let server_addr = start_server().await;
+    let mut client = connect(server_addr).await;
+
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    CONFIG.lock().unwrap().server.registration_timeout = 30;
+    assert_eq!(
+        "ERROR :Closing Link (Registration timed out)\r\n".as_bytes(),
+        &response
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_ping_timeout() {
+    {
+        let mut config = CONFIG.lock().unwrap();
+        config.server.ping_frequency = 1;
+        config.server.ping_timeout = 1;
+    }
+    let server_addr = start_server().await;
+    let mut client = connect(server_addr).await;
+    register(&mut client, "nick1".to_string()).await;
+
+    let mut response = [0; 14];
+    client.read_exact(&mut response).await.unwrap();
+    assert_eq!(
+        "PING server1\r\n".as_bytes(),
+        &response
+    );
+
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    {
+        let mut config = CONFIG.lock().unwrap();
+        config.server.ping_frequency = 120;
+        config.server.ping_timeout = 60;
+    }
+    assert_eq!(
+        "ERROR :Closing Link (Ping timeout: 2 seconds)\r\n".as_bytes(),
+        &response
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_quit() {
+    let server_addr = start_server().await;
+
+    let mut client1 = connect(server_addr).await;
+    register(&mut client1, "nick1".to_string()).await;
+    let mut client2 = connect(server_addr).await;
+    register(&mut client2, "nick2".to_string()).await;
+
+    client1.write_all(b"JOIN #channel1\r\n").await.unwrap();
+    read_line(&mut client1).await;
+    client2.write_all(b"JOIN #channel1\r\n").await.unwrap();
+    read_line(&mut client2).await;
+
+    client1.write_all(b"QUIT :bye\r\n").await.unwrap();
+    let mut response = Vec::new();
+    client1.read_to_end(&mut response).await.unwrap();
+    assert_eq!(
+        "ERROR :Closing Link (Quit: bye)\r\n".as_bytes(),
+        &response
+    );
+
+    assert_eq!(
+        ":nick1!nick1@127.0.0.1 QUIT :Quit: bye",
+        read_line(&mut client2).await
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_excess_flood() {
+    {
+        let mut config = CONFIG.lock().unwrap();
+        config.flood.burst = 2;
+        config.flood.recvq = 64;
+    }
+    let server_addr = start_server().await;
+    let mut client = connect(server_addr).await;
+
+    let flood: String = (0..20).map(|i| format!("PING token{}\r\n", i)).collect();
+    client.write_all(flood.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    {
+        let mut config = CONFIG.lock().unwrap();
+        config.flood.burst = 10;
+        config.flood.recvq = 8192;
+    }
+    assert_eq!(
+        ":server1 PONG token0\r\n:server1 PONG token1\r\nERROR :Closing Link (Excess Flood)\r\n".as_bytes(),
+        &response
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_zero_flood_rate() {
+    {
+        let mut config = CONFIG.lock().unwrap();
+        config.flood.burst = 2;
+        config.flood.rate = 0.0;
+        config.flood.recvq = 64;
+    }
+    let server_addr = start_server().await;
+    let mut client = connect(server_addr).await;
+
+    let flood: String = (0..20).map(|i| format!("PING token{}\r\n", i)).collect();
+    client.write_all(flood.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    {
+        let mut config = CONFIG.lock().unwrap();
+        config.flood.burst = 10;
+        config.flood.rate = 1.0;
+        config.flood.recvq = 8192;
+    }
+    assert_eq!(
+        ":server1 PONG token0\r\n:server1 PONG token1\r\nERROR :Closing Link (Excess Flood)\r\n".as_bytes(),
+        &response
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_oper() {
+    let server_addr = start_server().await;
+    let mut client = connect(server_addr).await;
+    register(&mut client, "nick1".to_string()).await;
+
+    client.write_all(b"OPER admin wrong\r\n").await.unwrap();
+    assert!(read_line(&mut client).await.starts_with(":server1 464 nick1"));
+
+    client.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 381 nick1 :You are now an IRC operator",
+        read_line(&mut client).await
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_class_max_per_ip() {
+    let classes = CONFIG.lock().unwrap().class.clone();
+    {
+        let mut config = CONFIG.lock().unwrap();
+        for class in config.class.iter_mut() {
+            class.max_per_ip = Some(1);
+        }
+    }
+    let server_addr = start_server().await;
+    let mut client1 = connect(server_addr).await;
+    client1.write_all(b"PING token\r\n").await.unwrap();
+    assert_eq!(":server1 PONG token", read_line(&mut client1).await);
+
+    let mut client2 = TcpStream::connect(server_addr).await.unwrap();
+    let mut response = Vec::new();
+    client2.read_to_end(&mut response).await.unwrap();
+    CONFIG.lock().unwrap().class = classes;
+    assert_eq!(
+        "ERROR :Closing Link (Too many connections from your host)\r\n".as_bytes(),
+        &response
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_stats_classes() {
+    let server_addr = start_server().await;
+    let mut client = connect(server_addr).await;
+    register(&mut client, "nick1".to_string()).await;
+
+    client.write_all(b"STATS y\r\n").await.unwrap();
+    assert!(read_line(&mut client).await.starts_with(":server1 481 nick1"));
+
+    client.write_all(b"OPER admin operpass\r\nSTATS y\r\n").await.unwrap();
+    read_line(&mut client).await;
+    assert_eq!(":server1 218 nick1 users 120 0 102400 1/1024", read_line(&mut client).await);
+    assert_eq!(":server1 219 nick1 y :End of /STATS report", read_line(&mut client).await);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_throttle() {
+    CONFIG.lock().unwrap().throttle.connections = 2;
+    let server_addr = start_server().await;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    oper.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut oper).await;
+
+    let _client2 = connect(server_addr).await;
+    let mut client3 = TcpStream::connect(server_addr).await.unwrap();
+    let mut response = Vec::new();
+    client3.read_to_end(&mut response).await.unwrap();
+    CONFIG.lock().unwrap().throttle.connections = 10;
+    assert_eq!(
+        "ERROR :Closing Link (Throttled: Reconnecting too fast)\r\n".as_bytes(),
+        &response
+    );
+
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Notice -- Throttling 127.0.0.1: more than 2 connections in 60 seconds, banned for 300 seconds",
+        read_line(&mut oper).await
+    );
+
+    oper.write_all(b"STATS t\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 249 nick1 :Throttle: 1 connections refused, 1 bans placed, 1 bans active",
+        read_line(&mut oper).await
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_kline() {
+    let server_addr = start_server().await;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    oper.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut oper).await;
+
+    let mut client = connect(server_addr).await;
+    register(&mut client, "nick2".to_string()).await;
+    client.write_all(b"PING token\r\n").await.unwrap();
+    read_line(&mut client).await;
+
+    oper.write_all(b"KLINE nick2@* :spamming\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick1 :Added permanent K-line for [nick2@*]",
+        read_line(&mut oper).await
+    );
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Notice -- nick1 added permanent K-line for [nick2@*]: spamming",
+        read_line(&mut oper).await
+    );
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    assert_eq!(
+        "ERROR :Closing Link (K-lined: spamming)\r\n".as_bytes(),
+        &response
+    );
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Notice -- Disconnecting nick2 (nick2@127.0.0.1): K-lined: spamming",
+        read_line(&mut oper).await
+    );
+
+    let mut client = connect(server_addr).await;
+    send_registration(&mut client, "nick2").await;
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    assert_eq!(
+        "ERROR :Closing Link (K-lined: spamming)\r\n".as_bytes(),
+        &response
+    );
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Notice -- Rejected nick2 (nick2@127.0.0.1) [127.0.0.1]: K-lined: spamming",
+        read_line(&mut oper).await
+    );
+
+    oper.write_all(b"STATS k\r\n").await.unwrap();
+    let line = read_line(&mut oper).await;
+    assert!(line.starts_with(":server1 216 nick1 nick2@* "));
+    assert!(line.ends_with(" 0 nick1 spamming"));
+    assert_eq!(":server1 219 nick1 k :End of /STATS report", read_line(&mut oper).await);
+
+    oper.write_all(b"UNKLINE nick2@*\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick1 :K-line for [nick2@*] is removed",
+        read_line(&mut oper).await
+    );
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Notice -- nick1 has removed the K-line for: [nick2@*]",
+        read_line(&mut oper).await
+    );
+    oper.write_all(b"STATS k\r\n").await.unwrap();
+    assert_eq!(":server1 219 nick1 k :End of /STATS report", read_line(&mut oper).await);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_kline_long_duration() {
+    let server_addr = start_server().await;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    oper.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut oper).await;
+
+    oper.write_all(b"KLINE 999999999999999999 nick2@* :spamming\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick1 :Added 999999999999999999 minutes K-line for [nick2@*]",
+        read_line(&mut oper).await
+    );
+    read_line(&mut oper).await;
+    oper.write_all(b"STATS k\r\n").await.unwrap();
+    assert!(read_line(&mut oper).await.ends_with(" 18446744073709551615 nick1 spamming"));
+    read_line(&mut oper).await;
+
+    oper.write_all(b"UNKLINE nick2@*\r\n").await.unwrap();
+    read_line(&mut oper).await;
+}
+
+#[serial]
+#[tokio::test]
+async fn test_dline() {
+    let server_addr = start_server().await;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    oper.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut oper).await;
+
+    oper.write_all(b"DLINE 127.0.0.0/8 :local\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick1 :Added permanent D-line for [127.0.0.0/8]",
+        read_line(&mut oper).await
+    );
+    read_line(&mut oper).await;
+    let mut response = Vec::new();
+    oper.read_to_end(&mut response).await.unwrap();
+    assert_eq!(
+        "ERROR :Closing Link (D-lined: local)\r\n".as_bytes(),
+        &response
+    );
+
+    let mut client = TcpStream::connect(server_addr).await.unwrap();
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    BANS.lock().unwrap().remove(BanKind::Dline, "127.0.0.0/8");
+    assert_eq!(
+        "ERROR :Closing Link (D-lined: local)\r\n".as_bytes(),
+        &response
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_nick_change() {
+    let server_addr = start_server().await;
+    let mut client1 = connect(server_addr).await;
+    register(&mut client1, "nick1".to_string()).await;
+    let mut client2 = connect(server_addr).await;
+    register(&mut client2, "nick2".to_string()).await;
+
+    client1.write_all(b"JOIN #channel1\r\n").await.unwrap();
+    read_line(&mut client1).await;
+    client2.write_all(b"JOIN #channel1\r\n").await.unwrap();
+    read_line(&mut client2).await;
+
+    client1.write_all(b"NICK nick3\r\n").await.unwrap();
+    assert_eq!(":nick1!nick1@127.0.0.1 NICK nick3", read_line(&mut client1).await);
+    assert_eq!(":nick1!nick1@127.0.0.1 NICK nick3", read_line(&mut client2).await);
+
+    client2.write_all(b"PRIVMSG nick3 hello\r\n").await.unwrap();
+    assert_eq!(":nick2!nick2@127.0.0.1 PRIVMSG nick3 hello", read_line(&mut client1).await);
+
+    client2.write_all(b"NICK nick3\r\nPRIVMSG nick3 again\r\n").await.unwrap();
+    assert_eq!(":server1 433 nick2 nick3 :Nickname is already in use", read_line(&mut client2).await);
+    assert_eq!(":nick2!nick2@127.0.0.1 PRIVMSG nick3 again", read_line(&mut client1).await);
+
+    // Nicknames compare without case.
+    client2.write_all(b"NICK NICK3\r\n").await.unwrap();
+    assert_eq!(":server1 433 nick2 NICK3 :Nickname is already in use", read_line(&mut client2).await);
+    client1.write_all(b"NICK Nick3\r\n").await.unwrap();
+    assert_eq!(":nick3!nick1@127.0.0.1 NICK Nick3", read_line(&mut client1).await);
+    assert_eq!(":nick3!nick1@127.0.0.1 NICK Nick3", read_line(&mut client2).await);
+    client2.write_all(b"PRIVMSG NICK3 hello\r\n").await.unwrap();
+    assert_eq!(":nick2!nick2@127.0.0.1 PRIVMSG NICK3 hello", read_line(&mut client1).await);
+
+    // Registering with a nickname in use waits for a free one.
+    let mut client3 = connect(server_addr).await;
+    send_registration(&mut client3, "NICK3").await;
+    assert_eq!(":server1 433 * NICK3 :Nickname is already in use", read_line(&mut client3).await);
+    client3.write_all(b"NICK nick4\r\n").await.unwrap();
+    read_welcome(&mut client3, "nick4").await;
+    client2.write_all(b"PRIVMSG nick3 again\r\n").await.unwrap();
+    assert_eq!(":nick2!nick2@127.0.0.1 PRIVMSG nick3 again", read_line(&mut client1).await);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_qline() {
+    let server_addr = start_server().await;
+    let mut client = connect(server_addr).await;
+    client.write_all(b"PASS password\r\nNICK NickServ\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 432 * NickServ :Erroneous Nickname: Reserved for services",
+        read_line(&mut client).await
+    );
+
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    oper.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut oper).await;
+    oper.write_all(b"RESV #warez* :No warez\r\n").await.unwrap();
+    read_line(&mut oper).await;
+    read_line(&mut oper).await;
+
+    client.write_all(b"NICK nick2\r\nUSER nick2 0 * nick2\r\nJOIN #warez-dl\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick2 :*** Couldn't look up your hostname",
+        read_line(&mut client).await
+    );
+    read_welcome(&mut client, "nick2").await;
+    assert_eq!(
+        ":server1 476 nick2 #warez-dl :Cannot join channel: No warez",
+        read_line(&mut client).await
+    );
+
+    oper.write_all(b"UNRESV #warez*\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick1 :Q-line for [#warez*] is removed",
+        read_line(&mut oper).await
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_xline() {
+    let server_addr = start_server().await;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    oper.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut oper).await;
+
+    oper.write_all(b"XLINE /^spam[0-9]+bot$/ :Spam bot\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick1 :Added permanent X-line for [/^spam[0-9]+bot$/]",
+        read_line(&mut oper).await
+    );
+
+    let mut client = connect(server_addr).await;
+    send_registration(&mut client, "spam42bot").await;
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    BANS.lock().unwrap().remove(BanKind::Xline, "/^spam[0-9]+bot$/");
+    assert_eq!(
+        "ERROR :Closing Link (X-lined: Spam bot)\r\n".as_bytes(),
+        &response
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_shun() {
+    let server_addr = start_server().await;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    oper.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut oper).await;
+
+    let mut client = connect(server_addr).await;
+    register(&mut client, "nick2".to_string()).await;
+    client.write_all(b"PING token\r\n").await.unwrap();
+    read_line(&mut client).await;
+
+    oper.write_all(b"SHUN 10 nick2@* :annoying\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick1 :Added 10 minutes shun for [nick2@*]",
+        read_line(&mut oper).await
+    );
+    read_line(&mut oper).await;
+
+    client.write_all(b"PRIVMSG nick1 hello\r\nPING token\r\n").await.unwrap();
+    assert_eq!(":server1 PONG token", read_line(&mut client).await);
+
+    oper.write_all(b"STATS s\r\n").await.unwrap();
+    let line = read_line(&mut oper).await;
+    assert!(line.starts_with(":server1 223 nick1 nick2@* "));
+    assert!(line.ends_with(" nick1 annoying"));
+    assert_eq!(":server1 219 nick1 s :End of /STATS report", read_line(&mut oper).await);
+
+    oper.write_all(b"UNSHUN nick2@*\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick1 :shun for [nick2@*] is removed",
+        read_line(&mut oper).await
+    );
+    read_line(&mut oper).await;
+
+    client.write_all(b"PRIVMSG nick1 hello\r\n").await.unwrap();
+    assert_eq!(":nick2!nick2@127.0.0.1 PRIVMSG nick1 hello", read_line(&mut oper).await);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_spamfilter() {
+    let server_addr = start_server().await;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    oper.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut oper).await;
+
+    let mut client = connect(server_addr).await;
+    register(&mut client, "nick2".to_string()).await;
+    client.write_all(b"PING token\r\n").await.unwrap();
+    read_line(&mut client).await;
+
+    oper.write_all(b"SPAMFILTER pc block 0 *buy*now* :Advertising\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick1 :Added spamfilter for [*buy*now*]",
+        read_line(&mut oper).await
+    );
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Notice -- nick1 added spamfilter for [*buy*now*] (pc block): Advertising",
+        read_line(&mut oper).await
+    );
+
+    client.write_all(b"PRIVMSG nick1 :buy pills now\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick2 :Message to nick1 blocked: Advertising",
+        read_line(&mut client).await
+    );
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Notice -- Spamfilter [*buy*now*] matched by nick2 (p to nick1): buy pills now, action: block",
+        read_line(&mut oper).await
+    );
+    client.write_all(b"PRIVMSG nick1 hello\r\n").await.unwrap();
+    assert_eq!(":nick2!nick2@127.0.0.1 PRIVMSG nick1 hello", read_line(&mut oper).await);
+
+    // Unregistered connections can't get around the filters.
+    let mut unregistered = connect(server_addr).await;
+    unregistered.write_all(b"PRIVMSG nick1 :buy pills now\r\nNOTICE nick1 :buy pills now\r\n").await.unwrap();
+    assert_eq!(":server1 451 * :You have not registered", read_line(&mut unregistered).await);
+    assert_eq!(":server1 451 * :You have not registered", read_line(&mut unregistered).await);
+
+    oper.write_all(b"STATS f\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 229 nick1 pc block 0 1 nick1 *buy*now* Advertising",
+        read_line(&mut oper).await
+    );
+    assert_eq!(":server1 219 nick1 f :End of /STATS report", read_line(&mut oper).await);
+
+    oper.write_all(b"SPAMFILTER p kill 0 /^die\\b/ :Go away\r\n").await.unwrap();
+    read_line(&mut oper).await;
+    read_line(&mut oper).await;
+    client.write_all(b"PRIVMSG nick1 :DIE now\r\n").await.unwrap();
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    assert_eq!(
+        "ERROR :Closing Link (Spamfilter: Go away)\r\n".as_bytes(),
+        &response
+    );
+
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Notice -- Spamfilter [/^die\\b/] matched by nick2 (p to nick1): DIE now, action: kill",
+        read_line(&mut oper).await
+    );
+
+    oper.write_all(b"UNSPAMFILTER *buy*now*\r\nUNSPAMFILTER /^die\\b/\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick1 :Spamfilter for [*buy*now*] is removed",
+        read_line(&mut oper).await
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_cloak() {
+    {
+        let mut config = CONFIG.lock().unwrap();
+        config.cloak.enabled = true;
+        config.cloak.key = "test-key".to_string();
+    }
+    let cloaked_host = cloak("127.0.0.1", "127.0.0.1".parse().unwrap());
+    let server_addr = start_server().await;
+    let mut client = connect(server_addr).await;
+    register(&mut client, "nick1".to_string()).await;
+    let hidden = read_line(&mut client).await;
+
+    client.write_all(b"MODE nick1 -x\r\n").await.unwrap();
+    let mode = read_line(&mut client).await;
+    let shown = read_line(&mut client).await;
+    client.write_all(b"MODE nick1\r\n").await.unwrap();
+    let modes = read_line(&mut client).await;
+
+    let network = cloaked_host.split_once('.').unwrap().1.to_string();
+    BANS.lock().unwrap().add(Ban::new(
+        BanKind::Kline, format!("*@*.{}", network), "Cloak ban".to_string(), "server1".to_string(), None));
+    let mut client = connect(server_addr).await;
+    send_registration(&mut client, "nick2").await;
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    BANS.lock().unwrap().remove(BanKind::Kline, &format!("*@*.{}", network));
+    CONFIG.lock().unwrap().cloak.enabled = false;
+
+    assert!(cloaked_host.ends_with(".IP"));
+    assert_eq!(format!(":server1 396 nick1 {} :is now your displayed host", cloaked_host), hidden);
+    assert_eq!(":nick1!nick1@127.0.0.1 MODE nick1 -x", mode);
+    assert_eq!(":server1 396 nick1 127.0.0.1 :is now your displayed host", shown);
+    assert_eq!(":server1 221 nick1 +", modes);
+    assert_eq!(
+        "ERROR :Closing Link (K-lined: Cloak ban)\r\n".as_bytes(),
+        &response
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_vhost() {
+    CONFIG.lock().unwrap().vhost = vec![Vhost {
+        login: "alice".to_string(),
+        password: "vhostpass".to_string(),
+        vhost: "staff.example.net".to_string(),
+        hosts: vec!["*".to_string()],
+    }];
+    let server_addr = start_server().await;
+    let mut client = connect(server_addr).await;
+    register(&mut client, "nick1".to_string()).await;
+
+    client.write_all(b"VHOST alice wrong\r\nVHOST alice vhostpass\r\n").await.unwrap();
+    let failed = read_line(&mut client).await;
+    let hidden = read_line(&mut client).await;
+    CONFIG.lock().unwrap().vhost = Vec::new();
+
+    assert_eq!(":server1 NOTICE nick1 :*** Login for vhost alice failed", failed);
+    assert_eq!(":server1 396 nick1 staff.example.net :is now your displayed host", hidden);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_whois() {
+    let server_addr = start_server().await;
+    let mut client1 = connect(server_addr).await;
+    register(&mut client1, "nick1".to_string()).await;
+    let mut client2 = connect(server_addr).await;
+    register(&mut client2, "nick2".to_string()).await;
+
+    client2.write_all(b"JOIN #channel1\r\n").await.unwrap();
+    read_line(&mut client2).await;
+
+    client1.write_all(b"WHOIS nick2,nick3\r\n").await.unwrap();
+    assert_eq!(":server1 311 nick1 nick2 nick2 127.0.0.1 * nick2", read_line(&mut client1).await);
+    assert_eq!(":server1 319 nick1 nick2 #channel1", read_line(&mut client1).await);
+    assert_eq!(":server1 312 nick1 nick2 server1 :IRC server", read_line(&mut client1).await);
+    assert!(read_line(&mut client1).await.starts_with(":server1 317 nick1 nick2 "));
+    assert_eq!(":server1 401 nick1 nick3 :No such nick/channel", read_line(&mut client1).await);
+    assert_eq!(":server1 318 nick1 nick2,nick3 :End of /WHOIS list", read_line(&mut client1).await);
+
+    client1.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut client1).await;
+    client2.write_all(b"WHOIS nick1\r\n").await.unwrap();
+    assert_eq!(":server1 311 nick2 nick1 nick1 127.0.0.1 * nick1", read_line(&mut client2).await);
+    assert_eq!(":server1 312 nick2 nick1 server1 :IRC server", read_line(&mut client2).await);
+    assert_eq!(":server1 313 nick2 nick1 :is an IRC operator", read_line(&mut client2).await);
+    assert!(read_line(&mut client2).await.starts_with(":server1 317 nick2 nick1 "));
+    assert_eq!(":server1 318 nick2 nick1 :End of /WHOIS list", read_line(&mut client2).await);
+
+    client1.write_all(b"WHOIS nick2\r\n").await.unwrap();
+    read_line(&mut client1).await;
+    assert_eq!(
+        ":server1 378 nick1 nick2 :is connecting from *@127.0.0.1 127.0.0.1",
+        read_line(&mut client1).await
+    );
+    for _ in 0..4 {
+        read_line(&mut client1).await;
+    }
+
+    client2.write_all(b"WHOIS a,b,c,d,e\r\nWHOIS NICK1\r\n").await.unwrap();
+    assert_eq!(":server1 407 nick2 a,b,c,d,e :Too many targets. The maximum is 4.", read_line(&mut client2).await);
+    assert_eq!(":server1 311 nick2 nick1 nick1 127.0.0.1 * nick1", read_line(&mut client2).await);
+    for _ in 0..4 {
+        read_line(&mut client2).await;
+    }
+
+    // Replies that client1 never reads must not hold up anyone else.
+    let whois: String = (0..10).map(|_| "WHOIS nick2,nick2,nick2,nick2\r\n").collect();
+    client1.write_all(whois.as_bytes()).await.unwrap();
+    client2.write_all(b"WHOIS nick2\r\n").await.unwrap();
+    let reply = tokio::time::timeout(Duration::from_secs(5), read_line(&mut client2)).await;
+    assert_eq!(":server1 311 nick2 nick2 nick2 127.0.0.1 * nick2", reply.unwrap());
+}
+
+#[serial]
+#[tokio::test]
+async fn test_whowas() {
+    let server_addr = start_server().await;
+    let mut client1 = connect(server_addr).await;
+    register(&mut client1, "nick1".to_string()).await;
+    let mut client2 = connect(server_addr).await;
+    register(&mut client2, "nick2".to_string()).await;
+
+    client2.write_all(b"NICK nick3\r\n").await.unwrap();
+    read_line(&mut client2).await;
+
+    client1.write_all(b"WHOWAS nick2\r\n").await.unwrap();
+    assert_eq!(":server1 314 nick1 nick2 nick2 127.0.0.1 * nick2", read_line(&mut client1).await);
+    assert_eq!(":server1 312 nick1 nick2 server1 :IRC server", read_line(&mut client1).await);
+    assert_eq!(":server1 369 nick1 nick2 :End of WHOWAS", read_line(&mut client1).await);
+
+    client1.write_all(b"WHOWAS nick4\r\n").await.unwrap();
+    assert_eq!(":server1 406 nick1 nick4 :There was no such nickname", read_line(&mut client1).await);
+    assert_eq!(":server1 369 nick1 nick4 :End of WHOWAS", read_line(&mut client1).await);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_who() {
+    let server_addr = start_server().await;
+    let mut client1 = connect(server_addr).await;
+    register(&mut client1, "nick1".to_string()).await;
+    let mut client2 = connect(server_addr).await;
+    register(&mut client2, "nick2".to_string()).await;
+    let mut client3 = connect(server_addr).await;
+    register(&mut client3, "nick3".to_string()).await;
+
+    client2.write_all(b"MODE nick2 +i\r\nJOIN #channel1\r\n").await.unwrap();
+    assert_eq!(":nick2!nick2@127.0.0.1 MODE nick2 +i", read_line(&mut client2).await);
+    read_line(&mut client2).await;
+    client3.write_all(b"JOIN #channel1\r\n").await.unwrap();
+    read_line(&mut client3).await;
+
+    // nick2 is invisible and shares no channel with nick1.
+    client1.write_all(b"WHO #channel1\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 352 nick1 #channel1 nick3 127.0.0.1 server1 nick3 H :0 nick3",
+        read_line(&mut client1).await
+    );
+    assert_eq!(":server1 315 nick1 #channel1 :End of WHO list", read_line(&mut client1).await);
+
+    client3.write_all(b"WHO nick*\r\n").await.unwrap();
+    for nick in ["nick1", "nick2", "nick3"] {
+        assert_eq!(
+            format!(":server1 352 nick3 * {nick} 127.0.0.1 server1 {nick} H :0 {nick}"),
+            read_line(&mut client3).await
+        );
+    }
+    assert_eq!(":server1 315 nick3 nick* :End of WHO list", read_line(&mut client3).await);
+
+    client1.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut client1).await;
+    client3.write_all(b"WHO * o%nfit,42\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 354 nick3 42 255.255.255.255 nick1 H*",
+        read_line(&mut client3).await
+    );
+    assert_eq!(":server1 315 nick3 * :End of WHO list", read_line(&mut client3).await);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_list() {
+    let server_addr = start_server().await;
+    let mut client1 = connect(server_addr).await;
+    register(&mut client1, "nick1".to_string()).await;
+    let mut client2 = connect(server_addr).await;
+    register(&mut client2, "nick2".to_string()).await;
+
+    client1.write_all(b"JOIN #channel1\r\nJOIN #channel2\r\n").await.unwrap();
+    read_line(&mut client1).await;
+    read_line(&mut client1).await;
+    client2.write_all(b"JOIN #channel1\r\nJOIN #other\r\n").await.unwrap();
+    read_line(&mut client2).await;
+    read_line(&mut client2).await;
+
+    client1.write_all(b"LIST\r\n").await.unwrap();
+    assert_eq!(":server1 321 nick1 Channel :Users  Name", read_line(&mut client1).await);
+    assert_eq!(":server1 322 nick1 #channel1 2 :", read_line(&mut client1).await);
+    assert_eq!(":server1 322 nick1 #channel2 1 :", read_line(&mut client1).await);
+    assert_eq!(":server1 322 nick1 #other 1 :", read_line(&mut client1).await);
+    assert_eq!(":server1 323 nick1 :End of /LIST", read_line(&mut client1).await);
+
+    client1.write_all(b"LIST #chan*,<2\r\n").await.unwrap();
+    read_line(&mut client1).await;
+    assert_eq!(":server1 322 nick1 #channel2 1 :", read_line(&mut client1).await);
+    read_line(&mut client1).await;
+
+    client1.write_all(b"LIST !#chan*,C<5\r\n").await.unwrap();
+    read_line(&mut client1).await;
+    assert_eq!(":server1 322 nick1 #other 1 :", read_line(&mut client1).await);
+    read_line(&mut client1).await;
+}
+
+#[serial]
+#[tokio::test]
+async fn test_away() {
+    let server_addr = start_server().await;
+    let mut client1 = connect(server_addr).await;
+    client1.write_all(b"CAP LS 302\r\nPASS password\r\nNICK nick1\r\nUSER nick1 0 * nick1\r\n").await.unwrap();
+    assert_eq!(":server1 CAP * LS away-notify", read_line(&mut client1).await);
+    client1.write_all(b"CAP REQ :away-notify\r\nCAP END\r\n").await.unwrap();
+    assert_eq!(":server1 CAP nick1 ACK away-notify", read_line(&mut client1).await);
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Couldn't look up your hostname",
+        read_line(&mut client1).await
+    );
+    read_welcome(&mut client1, "nick1").await;
+    let mut client2 = connect(server_addr).await;
+    register(&mut client2, "nick2".to_string()).await;
+
+    client1.write_all(b"JOIN #channel1\r\n").await.unwrap();
+    read_line(&mut client1).await;
+    client2.write_all(b"JOIN #channel1\r\n").await.unwrap();
+    read_line(&mut client2).await;
+
+    client2.write_all(b"AWAY :gone to lunch\r\n").await.unwrap();
+    assert_eq!(":server1 306 nick2 :You have been marked as being away", read_line(&mut client2).await);
+    assert_eq!(":nick2!nick2@127.0.0.1 AWAY :gone to lunch", read_line(&mut client1).await);
+
+    client1.write_all(b"PRIVMSG nick2 hello\r\n").await.unwrap();
+    assert_eq!(":nick1!nick1@127.0.0.1 PRIVMSG nick2 hello", read_line(&mut client2).await);
+    assert_eq!(":server1 301 nick1 nick2 :gone to lunch", read_line(&mut client1).await);
+
+    client1.write_all(b"WHO #channel1 %nf\r\n").await.unwrap();
+    assert_eq!(":server1 354 nick1 nick1 H", read_line(&mut client1).await);
+    assert_eq!(":server1 354 nick1 nick2 G", read_line(&mut client1).await);
+    read_line(&mut client1).await;
+
+    client2.write_all(b"AWAY\r\n").await.unwrap();
+    assert_eq!(":server1 305 nick2 :You are no longer marked as being away", read_line(&mut client2).await);
+    assert_eq!(":nick2!nick2@127.0.0.1 AWAY", read_line(&mut client1).await);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_user_modes() {
+    let server_addr = start_server().await;
+    let mut client1 = connect(server_addr).await;
+    register(&mut client1, "nick1".to_string()).await;
+    let mut client2 = connect(server_addr).await;
+    register(&mut client2, "nick2".to_string()).await;
+
+    // +o can't be set with MODE and is silently skipped.
+    client1.write_all(b"MODE nick1 +oiwB\r\nMODE nick1\r\n").await.unwrap();
+    assert_eq!(":nick1!nick1@127.0.0.1 MODE nick1 +i+w+B", read_line(&mut client1).await);
+    assert_eq!(":server1 221 nick1 +Biw", read_line(&mut client1).await);
+
+    client2.write_all(b"WHOIS nick1\r\n").await.unwrap();
+    read_line(&mut client2).await;
+    read_line(&mut client2).await;
+    assert_eq!(":server1 335 nick2 nick1 :is a Bot", read_line(&mut client2).await);
+
+    client2.write_all(b"MODE nick2 +R\r\n").await.unwrap();
+    read_line(&mut client2).await;
+    client1.write_all(b"PRIVMSG nick2 hello\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 486 nick1 nick2 :You must identify to a registered nick to private message this user",
+        read_line(&mut client1).await
+    );
+
+    client1.write_all(b"OPER admin operpass\r\nMODE nick1 -o\r\n").await.unwrap();
+    read_line(&mut client1).await;
+    assert_eq!(":nick1!nick1@127.0.0.1 MODE nick1 -o-s", read_line(&mut client1).await);
+
+    client1.write_all(b"MODE nick2 +i\r\nMODE nick3\r\nMODE #channel1\r\n").await.unwrap();
+    assert_eq!(":server1 502 nick1 :Cant change mode for other users", read_line(&mut client1).await);
+    assert_eq!(":server1 401 nick1 nick3 :No such nick/channel", read_line(&mut client1).await);
+    assert_eq!(":server1 403 nick1 #channel1 :No such channel", read_line(&mut client1).await);
+
+    client2.write_all(b"JOIN #channel1\r\n").await.unwrap();
+    while read_line(&mut client2).await != ":nick2!nick2@127.0.0.1 JOIN #channel1" {}
+    client1.write_all(b"MODE #Channel1\r\n").await.unwrap();
+    assert_eq!(":server1 324 nick1 #Channel1 +", read_line(&mut client1).await);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_snomask() {
+    let server_addr = start_server().await;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+
+    oper.write_all(b"MODE nick1 +s\r\n").await.unwrap();
+    oper.write_all(b"OPER admin operpass\r\nMODE nick1\r\n").await.unwrap();
+    read_line(&mut oper).await;
+    assert_eq!(":server1 221 nick1 +os", read_line(&mut oper).await);
+
+    oper.write_all(b"MODE nick1 +s +cn-bfkrS\r\n").await.unwrap();
+    assert_eq!(":server1 008 nick1 +cn :Server notice mask", read_line(&mut oper).await);
+    oper.write_all(b"MODE nick1 +s +cZ\r\nMODE nick1 +s +cFk\r\n").await.unwrap();
+    assert_eq!(":server1 501 nick1 :Unknown server notice mask Z", read_line(&mut oper).await);
+    assert_eq!(":server1 008 nick1 +Fckn :Server notice mask", read_line(&mut oper).await);
+
+    let mut client = connect(server_addr).await;
+    register(&mut client, "nick2".to_string()).await;
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Notice -- Client connecting: nick2 (nick2@127.0.0.1) [127.0.0.1] {users} [nick2]",
+        read_line(&mut oper).await
+    );
+    client.write_all(b"NICK nick3\r\nQUIT :bye\r\n").await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Notice -- Nick change: From nick2 to nick3 [nick2@127.0.0.1]",
+        read_line(&mut oper).await
+    );
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Notice -- Client exiting: nick3 (nick2@127.0.0.1) [Quit: bye]",
+        read_line(&mut oper).await
+    );
+
+    oper.write_all(b"MODE nick1 -s\r\n").await.unwrap();
+    assert_eq!(":nick1!nick1@127.0.0.1 MODE nick1 -s", read_line(&mut oper).await);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_wallops() {
+    let server_addr = start_server().await;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    let mut client1 = connect(server_addr).await;
+    register(&mut client1, "nick2".to_string()).await;
+    let mut client2 = connect(server_addr).await;
+    register(&mut client2, "nick3".to_string()).await;
+
+    client1.write_all(b"NOTICE $* :spam\r\nWALLOPS :hello\r\nMODE nick2 +w\r\n").await.unwrap();
+    assert!(read_line(&mut client1).await.starts_with(":server1 481 nick2"));
+    read_line(&mut client1).await;
+    oper.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut oper).await;
+
+    oper.write_all(b"WALLOPS :hello\r\nGLOBOPS :opers only\r\nNOTICE $* :maintenance\r\n").await.unwrap();
+    assert_eq!(":nick1!nick1@127.0.0.1 WALLOPS hello", read_line(&mut client1).await);
+    assert_eq!(":nick1!nick1@127.0.0.1 WALLOPS :OPERWALL - opers only", read_line(&mut oper).await);
+    assert_eq!(":nick1!nick1@127.0.0.1 NOTICE $* maintenance", read_line(&mut oper).await);
+    assert_eq!(":nick1!nick1@127.0.0.1 NOTICE $* maintenance", read_line(&mut client1).await);
+    assert_eq!(":nick1!nick1@127.0.0.1 NOTICE $* maintenance", read_line(&mut client2).await);
+
+    oper.write_all(b"WALLOPS 4\r\nWALLOPS 5\r\nWALLOPS 6\r\n").await.unwrap();
+    assert_eq!(":server1 263 nick1 WALLOPS :Please wait a while and try again.", read_line(&mut oper).await);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_motd() {
+    let server_addr = start_server().await;
+    let motd_file = std::env::temp_dir().join("irc_server_test_motd.txt");
+    let class_motd_file = std::env::temp_dir().join("irc_server_test_class_motd.txt");
+    std::fs::write(&motd_file, "line one\nline two\n").unwrap();
+    std::fs::write(&class_motd_file, "for users\n").unwrap();
+    CONFIG.lock().unwrap().server.motd_file = Some(motd_file.to_str().unwrap().to_string());
+
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    oper.write_all(b"REHASH MOTD\r\n").await.unwrap();
+    assert!(read_line(&mut oper).await.starts_with(":server1 481 nick1"));
+    oper.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut oper).await;
+    oper.write_all(b"REHASH MOTD\r\nMOTD\r\nMOTD server2\r\n").await.unwrap();
+    assert_eq!(":server1 382 nick1 config.toml Rehashing", read_line(&mut oper).await);
+    assert_eq!(":server1 375 nick1 :- server1 Message of the day - ", read_line(&mut oper).await);
+    assert_eq!(":server1 372 nick1 :- line one", read_line(&mut oper).await);
+    assert_eq!(":server1 372 nick1 :- line two", read_line(&mut oper).await);
+    assert_eq!(":server1 376 nick1 :End of /MOTD command.", read_line(&mut oper).await);
+    assert_eq!(":server1 402 nick1 server2 :No such server", read_line(&mut oper).await);
+
+    CONFIG.lock().unwrap().class[0].motd_file = Some(class_motd_file.to_str().unwrap().to_string());
+    oper.write_all(b"REHASH MOTD\r\n").await.unwrap();
+    read_line(&mut oper).await;
+    let mut client = connect(server_addr).await;
+    send_registration(&mut client, "nick2").await;
+    while !read_line(&mut client).await.starts_with(":server1 005 nick2 ") {}
+    assert_eq!(":server1 375 nick2 :- server1 Message of the day - ", read_line(&mut client).await);
+    assert_eq!(":server1 372 nick2 :- for users", read_line(&mut client).await);
+    assert_eq!(":server1 376 nick2 :End of /MOTD command.", read_line(&mut client).await);
+
+    // A full rehash goes back to config.toml, which has no MOTD.
+    oper.write_all(b"REHASH\r\nMOTD\r\n").await.unwrap();
+    assert_eq!(":server1 382 nick1 config.toml Rehashing", read_line(&mut oper).await);
+    assert_eq!(":server1 422 nick1 :MOTD File is missing", read_line(&mut oper).await);
+    std::fs::remove_file(motd_file).unwrap();
+    std::fs::remove_file(class_motd_file).unwrap();
+}
+
+#[serial]
+#[tokio::test]
+async fn test_server_queries() {
+    let server_addr = start_server().await;
+    let mut client1 = connect(server_addr).await;
+    register(&mut client1, "nick1".to_string()).await;
+    let mut client2 = connect(server_addr).await;
+    register(&mut client2, "nick2".to_string()).await;
+    let _unregistered = connect(server_addr).await;
+
+    client2.write_all(b"MODE nick2 +i\r\n").await.unwrap();
+    read_line(&mut client2).await;
+    client1.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut client1).await;
+
+    client1.write_all(b"LUSERS\r\n").await.unwrap();
+    assert_eq!(":server1 251 nick1 :There are 1 users and 1 invisible on 1 servers", read_line(&mut client1).await);
+    assert_eq!(":server1 252 nick1 1 :operator(s) online", read_line(&mut client1).await);
+    assert_eq!(":server1 253 nick1 1 :unknown connection(s)", read_line(&mut client1).await);
+    assert_eq!(":server1 254 nick1 0 :channels formed", read_line(&mut client1).await);
+    assert_eq!(":server1 255 nick1 :I have 2 clients and 0 servers", read_line(&mut client1).await);
+    assert_eq!(":server1 265 nick1 2 2 :Current local users 2, max 2", read_line(&mut client1).await);
+    assert_eq!(":server1 266 nick1 2 2 :Current global users 2, max 2", read_line(&mut client1).await);
+
+    client2.write_all(b"VERSION\r\n").await.unwrap();
+    assert_eq!(":server1 351 nick2 irc_server-0.1.0 server1 :", read_line(&mut client2).await);
+    assert!(read_line(&mut client2).await.starts_with(":server1 005 nick2 AWAYLEN=200 CASEMAPPING=ascii "));
+
+    client2.write_all(b"TIME\r\nTIME server2\r\n").await.unwrap();
+    assert!(read_line(&mut client2).await.starts_with(":server1 391 nick2 server1 :"));
+    assert_eq!(":server1 402 nick2 server2 :No such server", read_line(&mut client2).await);
+
+    client2.write_all(b"ADMIN\r\n").await.unwrap();
+    assert_eq!(":server1 256 nick2 server1 :Administrative info", read_line(&mut client2).await);
+    assert_eq!(":server1 257 nick2 :Example City, Earth", read_line(&mut client2).await);
+    assert_eq!(":server1 258 nick2 :Example IRC network", read_line(&mut client2).await);
+    assert_eq!(":server1 259 nick2 admin@example.com", read_line(&mut client2).await);
+
+    client2.write_all(b"INFO\r\n").await.unwrap();
+    assert_eq!(":server1 371 nick2 :irc_server-0.1.0 -- IRC server", read_line(&mut client2).await);
+    assert!(read_line(&mut client2).await.starts_with(":server1 371 nick2 :On-line since "));
+    assert_eq!(":server1 374 nick2 :End of /INFO list.", read_line(&mut client2).await);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_isupport() {
+    let server_addr = start_server().await;
+    CONFIG.lock().unwrap().server.nicklen = 10;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    let mut unregistered = connect(server_addr).await;
+    unregistered.write_all(b"PASS password\r\nNICK nickname_too_long\r\n").await.unwrap();
+    assert_eq!(":server1 432 * nickname_too_long :Erroneous Nickname", read_line(&mut unregistered).await);
+    let mut client = connect(server_addr).await;
+    send_registration(&mut client, "nick2").await;
+    while !read_line(&mut client).await.starts_with(":server1 004 nick2 ") {}
+    assert_eq!(":server1 005 nick2 AWAYLEN=200 CASEMAPPING=ascii CHANNELLEN=50 CHANMODES=,,, CHANTYPES=# ELIST=CMNU \
+        NETWORK=ExampleNet NICKLEN=10 PREFIX= SAFELIST TARGMAX=JOIN:,PRIVMSG:4,WHOIS:4 WHOX :are supported by this server",
+        read_line(&mut client).await);
+    read_line(&mut client).await;
+
+    client.write_all(b"NICK nickname_too_long\r\nJOIN channel1\r\nPRIVMSG a,b,c,d,e :hello\r\n").await.unwrap();
+    assert_eq!(":server1 432 nick2 nickname_too_long :Erroneous Nickname", read_line(&mut client).await);
+    assert_eq!(":server1 476 nick2 channel1 :Bad Channel Mask", read_line(&mut client).await);
+    assert_eq!(":server1 407 nick2 a,b,c,d,e :Too many targets. The maximum is 4.", read_line(&mut client).await);
+
+    // Rehashing goes back to the NICKLEN in config.toml.
+    oper.write_all(b"OPER admin operpass\r\nREHASH\r\n").await.unwrap();
+    read_line(&mut oper).await;
+    assert_eq!(":server1 382 nick1 config.toml Rehashing", read_line(&mut oper).await);
+    assert_eq!(":server1 005 nick2 NICKLEN=30 :are supported by this server", read_line(&mut client).await);
+}
+
+#[serial]
+#[tokio::test]
+async fn test_sendq_exceeded() {
+    let server_addr = start_server().await;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    oper.write_all(b"OPER admin operpass\r\nMODE nick1 +s +c\r\n").await.unwrap();
+    read_line(&mut oper).await;
+    read_line(&mut oper).await;
+
+    let sendq = CONFIG.lock().unwrap().class[0].sendq;
+    CONFIG.lock().unwrap().class[0].sendq = 4096;
+    let mut reader = connect(server_addr).await;
+    register(&mut reader, "nick2".to_string()).await;
+    CONFIG.lock().unwrap().class[0].sendq = sendq;
+    read_line(&mut oper).await;
+    let mut client = connect(server_addr).await;
+    register(&mut client, "nick3".to_string()).await;
+    read_line(&mut oper).await;
+
+    // nick2 never reads, so once the socket buffers fill its sendq
+    // overflows and it is dropped, while the bridge keeps serving everyone
+    // else.
+    let line = format!("PRIVMSG nick2 :{}\r\n", "x".repeat(400));
+    for _ in 0..20000 {
+        oper.write_all(line.as_bytes()).await.unwrap();
+    }
+    let notice = tokio::time::timeout(Duration::from_secs(10), read_line(&mut oper)).await;
+    assert_eq!(
+        Ok(":server1 NOTICE nick1 :*** Notice -- Client exiting: nick2 (nick2@127.0.0.1) [Max SendQ exceeded]".to_string()),
+        notice
+    );
+    client.write_all(b"WHOIS nick2\r\n").await.unwrap();
+    assert_eq!(":server1 401 nick3 nick2 :No such nick/channel", read_line(&mut client).await);
+    assert_eq!(":server1 318 nick3 nick2 :End of /WHOIS list", read_line(&mut client).await);
+
+    let mut received = Vec::new();
+    let read = tokio::time::timeout(Duration::from_secs(10), reader.read_to_end(&mut received)).await;
+    assert!(read.is_ok());
+    assert!(String::from_utf8_lossy(&received).ends_with("ERROR :Closing Link (Max SendQ exceeded)\r\n"));
+}
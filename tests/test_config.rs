@@ -0,0 +1,24 @@
+use std::env::temp_dir;
+use std::fs::{read_to_string, remove_file, write};
+
+use irc_server::config::Config;
+
+
+#[test]
+fn test_cloak_key() {
+    let path = temp_dir().join("irc_server_test_config.toml").to_string_lossy().to_string();
+    let shipped = read_to_string("config.toml").unwrap();
+    assert!(Config::load("config.toml").is_ok());
+
+    // Enabling cloaks without changing the shipped key is refused.
+    write(&path, shipped.replace("enabled = false\nkey", "enabled = true\nkey")).unwrap();
+    assert!(Config::load(&path).is_err());
+
+    write(&path, shipped.replace("enabled = false\nkey = \"change-me-to-a-long-random-string\"", "enabled = true\nkey = \"\"")).unwrap();
+    assert!(Config::load(&path).is_err());
+
+    write(&path, shipped.replace("enabled = false\nkey = \"change-me-to-a-long-random-string\"", "enabled = true\nkey = \"s3cr3t\"")).unwrap();
+    assert!(Config::load(&path).is_ok());
+
+    let _ = remove_file(&path);
+}
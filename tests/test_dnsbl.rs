@@ -0,0 +1,96 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use serial_test::serial;
+
+use irc_server::config::{Dnsbl, DnsblAction, CONFIG};
+use irc_server::server::start_server_with_resolver;
+
+mod common;
+use common::{connect, read_line, register, send_registration, StubResolver};
+
+
+fn stub_resolver(reply: Ipv4Addr, delay: Duration) -> Arc<StubResolver> {
+    let ipv4 = HashMap::from([("1.0.0.127.dnsbl.test".to_string(), reply)]);
+    return Arc::new(StubResolver { ipv4, delay, ..Default::default() });
+}
+
+fn zone(replies: HashMap<String, DnsblAction>) -> Dnsbl {
+    return Dnsbl {
+        zone: "dnsbl.test".to_string(),
+        reason: "Listed in the test zone".to_string(),
+        action: DnsblAction::Reject,
+        replies,
+    };
+}
+
+#[serial]
+#[tokio::test]
+async fn test_dnsbl_reject() {
+    CONFIG.lock().unwrap().dnsbl = vec![zone(HashMap::new())];
+    let server_addr = start_server_with_resolver(stub_resolver(Ipv4Addr::new(127, 0, 0, 2), Duration::ZERO)).await;
+
+    let mut client = connect(server_addr).await;
+    send_registration(&mut client, "nick1").await;
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    CONFIG.lock().unwrap().dnsbl = Vec::new();
+
+    assert_eq!(
+        "ERROR :Closing Link (DNSBL: Listed in the test zone)\r\n".as_bytes(),
+        &response
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_dnsbl_warn() {
+    let server_addr = start_server_with_resolver(stub_resolver(Ipv4Addr::new(127, 0, 0, 3), Duration::ZERO)).await;
+    let mut oper = connect(server_addr).await;
+    register(&mut oper, "nick1".to_string()).await;
+    oper.write_all(b"OPER admin operpass\r\n").await.unwrap();
+    read_line(&mut oper).await;
+
+    let replies = HashMap::from([("127.0.0.3".to_string(), DnsblAction::Warn)]);
+    CONFIG.lock().unwrap().dnsbl = vec![zone(replies)];
+    let mut client = connect(server_addr).await;
+    register(&mut client, "nick2".to_string()).await;
+    client.write_all(b"PING token\r\n").await.unwrap();
+    let pong = read_line(&mut client).await;
+    CONFIG.lock().unwrap().dnsbl = Vec::new();
+
+    assert_eq!(":server1 PONG token", pong);
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Notice -- nick2 [127.0.0.1] is listed in dnsbl.test (127.0.0.3)",
+        read_line(&mut oper).await
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_dnsbl_timeout() {
+    let dns_timeout = CONFIG.lock().unwrap().server.dns_timeout;
+    {
+        let mut config = CONFIG.lock().unwrap();
+        config.dnsbl = vec![zone(HashMap::new())];
+        config.server.dns_timeout = 1;
+    }
+    let server_addr = start_server_with_resolver(stub_resolver(Ipv4Addr::new(127, 0, 0, 2), Duration::from_secs(10))).await;
+
+    let start = Instant::now();
+    let mut client = connect(server_addr).await;
+    register(&mut client, "nick1".to_string()).await;
+    client.write_all(b"PING token\r\n").await.unwrap();
+    let pong = read_line(&mut client).await;
+    {
+        let mut config = CONFIG.lock().unwrap();
+        config.dnsbl = Vec::new();
+        config.server.dns_timeout = dns_timeout;
+    }
+
+    assert_eq!(":server1 PONG token", pong);
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
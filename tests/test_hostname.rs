@@ -0,0 +1,61 @@
+use tokio::io::AsyncReadExt;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
+use serial_test::serial;
+
+use irc_server::ban::{Ban, BanKind, BANS};
+use irc_server::server::start_server_with_resolver;
+
+mod common;
+use common::{connect, read_line, write_registration, StubResolver};
+
+
+fn stub_resolver(forward: IpAddr) -> Arc<StubResolver> {
+    let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    return Arc::new(StubResolver {
+        ptr: HashMap::from([(localhost, "client.example.com".to_string())]),
+        forward: HashMap::from([("client.example.com".to_string(), forward)]),
+        ..Default::default()
+    });
+}
+
+#[serial]
+#[tokio::test]
+async fn test_hostname_found() {
+    let server_addr = start_server_with_resolver(stub_resolver(IpAddr::V4(Ipv4Addr::LOCALHOST))).await;
+    let mut client = connect(server_addr).await;
+    write_registration(&mut client, "nick1").await;
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Found your hostname",
+        read_line(&mut client).await
+    );
+
+    BANS.lock().unwrap().add(Ban::new(
+        BanKind::Kline, "*@*.example.com".to_string(), "Hostname ban".to_string(), "server1".to_string(), None));
+    let mut client = connect(server_addr).await;
+    write_registration(&mut client, "nick2").await;
+    assert_eq!(
+        ":server1 NOTICE nick2 :*** Found your hostname",
+        read_line(&mut client).await
+    );
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    BANS.lock().unwrap().remove(BanKind::Kline, "*@*.example.com");
+    assert_eq!(
+        "ERROR :Closing Link (K-lined: Hostname ban)\r\n".as_bytes(),
+        &response
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_hostname_mismatch() {
+    let server_addr = start_server_with_resolver(stub_resolver(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))).await;
+    let mut client = connect(server_addr).await;
+    write_registration(&mut client, "nick1").await;
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Your forward and reverse DNS do not match, ignoring hostname",
+        read_line(&mut client).await
+    );
+}
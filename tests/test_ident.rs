@@ -0,0 +1,104 @@
+use tokio::{io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader}, net::{TcpListener, TcpStream}};
+use std::net::SocketAddr;
+use serial_test::serial;
+
+use irc_server::ban::{Ban, BanKind, BANS};
+use irc_server::config::CONFIG;
+use irc_server::server::start_server;
+
+mod common;
+use common::{connect, read_line, send_registration};
+
+
+/// Answers a single ident query with `userid` and returns the query.
+async fn fake_identd(userid: &'static str) -> (u16, tokio::task::JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let identd = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let mut query = String::new();
+        stream.read_line(&mut query).await.unwrap();
+        let query = query.trim_end().to_string();
+        stream.write_all(format!("{} : USERID : UNIX : {}\r\n", query, userid).as_bytes()).await.unwrap();
+        return query;
+    });
+    return (port, identd);
+}
+
+/// Connects and reads the notices sent while ident is enabled.
+async fn connect_with_ident(server_addr: SocketAddr) -> TcpStream {
+    let mut stream = connect(server_addr).await;
+    assert_eq!(
+        ":server1 NOTICE * :*** Checking Ident",
+        read_line(&mut stream).await
+    );
+    return stream;
+}
+
+#[serial]
+#[tokio::test]
+async fn test_ident() {
+    let (port, identd) = fake_identd("alice").await;
+    {
+        let mut config = CONFIG.lock().unwrap();
+        config.ident.enabled = true;
+        config.ident.port = port;
+    }
+    BANS.lock().unwrap().add(Ban::new(
+        BanKind::Kline, "alice@*".to_string(), "Banned by ident".to_string(), "server1".to_string(), None));
+    let server_addr = start_server().await;
+
+    let mut client = connect_with_ident(server_addr).await;
+    send_registration(&mut client, "nick1").await;
+    let query = identd.await.unwrap();
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** Got Ident response",
+        read_line(&mut client).await
+    );
+
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    BANS.lock().unwrap().remove(BanKind::Kline, "alice@*");
+    CONFIG.lock().unwrap().ident.enabled = false;
+    assert_eq!(
+        "ERROR :Closing Link (K-lined: Banned by ident)\r\n".as_bytes(),
+        &response
+    );
+    assert_eq!(
+        format!("{}, {}", client.local_addr().unwrap().port(), server_addr.port()),
+        query
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_ident_no_response() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    {
+        let mut config = CONFIG.lock().unwrap();
+        config.ident.enabled = true;
+        config.ident.port = port;
+    }
+    BANS.lock().unwrap().add(Ban::new(
+        BanKind::Kline, "~nick1@*".to_string(), "Unverified".to_string(), "server1".to_string(), None));
+    let server_addr = start_server().await;
+
+    let mut client = connect_with_ident(server_addr).await;
+    send_registration(&mut client, "nick1").await;
+    assert_eq!(
+        ":server1 NOTICE nick1 :*** No Ident response",
+        read_line(&mut client).await
+    );
+
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).await.unwrap();
+    BANS.lock().unwrap().remove(BanKind::Kline, "~nick1@*");
+    CONFIG.lock().unwrap().ident.enabled = false;
+    assert_eq!(
+        "ERROR :Closing Link (K-lined: Unverified)\r\n".as_bytes(),
+        &response
+    );
+}
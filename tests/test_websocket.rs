@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use irc_proto::enable_logging;
+use serial_test::serial;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::{Error, Message as Frame};
+
+use irc_server::config::CONFIG;
+use irc_server::server::start_websocket_server;
+
+
+#[serial]
+#[tokio::test]
+async fn test_websocket_ping() {
+    enable_logging();
+    let websocket_addr = start_websocket_server(Vec::new()).await;
+
+    let mut request = format!("ws://{}", websocket_addr).into_client_request().unwrap();
+    request.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static("text.ircv3.net"));
+    let (mut client, response) = connect_async(request).await.unwrap();
+    assert_eq!(
+        "text.ircv3.net",
+        response.headers()["Sec-WebSocket-Protocol"]
+    );
+
+    assert_eq!(
+        Frame::text(":server1 NOTICE * :*** Looking up your hostname..."),
+        client.next().await.unwrap().unwrap()
+    );
+    client.send(Frame::text("PING token")).await.unwrap();
+    let frame = client.next().await.unwrap().unwrap();
+    assert_eq!(
+        Frame::text(":server1 PONG token"),
+        frame
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_websocket_binary() {
+    let websocket_addr = start_websocket_server(Vec::new()).await;
+
+    let mut request = format!("ws://{}", websocket_addr).into_client_request().unwrap();
+    request.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static("binary.ircv3.net"));
+    let (mut client, _) = connect_async(request).await.unwrap();
+
+    assert_eq!(
+        Frame::binary(b":server1 NOTICE * :*** Looking up your hostname...".to_vec()),
+        client.next().await.unwrap().unwrap()
+    );
+    client.send(Frame::binary(b"PING token".to_vec())).await.unwrap();
+    let frame = client.next().await.unwrap().unwrap();
+    assert_eq!(
+        Frame::binary(b":server1 PONG token".to_vec()),
+        frame
+    );
+}
+
+#[serial]
+#[tokio::test]
+async fn test_websocket_oversized_frame() {
+    let websocket_addr = start_websocket_server(Vec::new()).await;
+
+    let request = format!("ws://{}", websocket_addr).into_client_request().unwrap();
+    let (mut client, _) = connect_async(request).await.unwrap();
+    client.next().await.unwrap().unwrap();
+
+    client.send(Frame::text(format!("PING {}", "x".repeat(65536)))).await.unwrap();
+    let frame = tokio::time::timeout(Duration::from_secs(5), client.next()).await.unwrap();
+    assert!(!matches!(frame, Some(Ok(Frame::Text(_)))));
+}
+
+#[serial]
+#[tokio::test]
+async fn test_websocket_origin() {
+    let websocket_addr = start_websocket_server(vec!["https://chat.example.com".to_string()]).await;
+
+    let mut request = format!("ws://{}", websocket_addr).into_client_request().unwrap();
+    request.headers_mut().insert("Origin", HeaderValue::from_static("https://evil.example.com"));
+    match connect_async(request).await {
+        Err(Error::Http(response)) => assert_eq!(StatusCode::FORBIDDEN, response.status()),
+        _ => panic!("connection from disallowed origin was accepted"),
+    }
+
+    let mut request = format!("ws://{}", websocket_addr).into_client_request().unwrap();
+    request.headers_mut().insert("Origin", HeaderValue::from_static("https://chat.example.com"));
+    assert!(connect_async(request).await.is_ok());
+}
+
+#[serial]
+#[tokio::test]
+async fn test_websocket_handshake_timeout() {
+    CONFIG.lock().unwrap().server.registration_timeout = 1;
+    let websocket_addr = start_websocket_server(Vec::new()).await;
+
+    let mut stream = TcpStream::connect(websocket_addr).await.unwrap();
+    let mut response = Vec::new();
+    let closed = tokio::time::timeout(Duration::from_secs(3), stream.read_to_end(&mut response)).await;
+    CONFIG.lock().unwrap().server.registration_timeout = 30;
+    assert!(closed.is_ok());
+    assert!(response.is_empty());
+}